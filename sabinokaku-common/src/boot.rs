@@ -0,0 +1,293 @@
+use std::error::Error;
+use std::ffi::c_void;
+use std::mem::{size_of, size_of_val};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use netcorehost::pdcstring::{PdChar, PdCString};
+use serde::Serialize;
+
+use crate::config::VulkanEntryPoint;
+use crate::services::SabinokakuServices;
+
+/// The version of the boot info filled in by this version of sabinokaku.
+pub const BOOT_INFO_VERSION: u32 = 1;
+
+/// What booted the runtime.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootTrigger {
+    /// `libkaku.so` was preloaded, and booted before the `main` of the host process.
+    Preload = 1,
+    /// `kaku.dll` was loaded, and booted from `DllMain`.
+    DllMain = 2,
+    /// The Vulkan layer booted after the first `vkCreateInstance`.
+    VkCreateInstance = 3,
+    /// The Vulkan layer booted after the first `vkCreateDevice`.
+    VkCreateDevice = 4,
+    /// A host booted deliberately through the C API.
+    Api = 5,
+}
+
+/// How far booting the runtime in this process has come. The runtime can only be booted once per process.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootStatus {
+    NotBooted = 0,
+    Booting = 1,
+    /// Booted, and every entry point that was called returned zero.
+    Booted = 2,
+    /// Booting failed, or an entry point failed or returned non-zero.
+    Failed = 3,
+}
+
+/// Passed to every managed entry point as `args`, with `sizeBytes` set to its size.
+///
+/// Fields are only ever added at the end, along with an increase of `version`, so a payload built against
+/// an older version can read any newer one. Strings are null-terminated platform strings, UTF-8 on Linux and
+/// UTF-16 on Windows, and are null if unknown.
+///
+/// Everything the struct points to stays valid until managed code frees it with `free_args` and every entry point
+/// has returned, or for the lifetime of the process if it is never freed.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SabinokakuBootInfo {
+    /// The version of the boot info when the struct was filled in.
+    pub version: u32,
+    /// The size of the struct in bytes, as filled in.
+    pub size: u32,
+    pub trigger: BootTrigger,
+    pub host_pid: u32,
+    pub config_path: *const PdChar,
+    pub module_path: *const PdChar,
+    /// The arguments of the host process, including the executable.
+    pub host_argc: usize,
+    pub host_argv: *const *const PdChar,
+    /// The strings of `arg` directives, followed by those of the host process if they are forwarded.
+    pub argc: usize,
+    pub argv: *const *const PdChar,
+    /// Specific to the trigger, such as the `VkInstance` and `VkDevice` handles for Vulkan triggers, or null.
+    pub trigger_data: *const c_void,
+    pub trigger_data_size: usize,
+    /// Frees the boot info, the same as the exported `sabinokaku_free_args`.
+    pub free_args: unsafe extern "C" fn(*const SabinokakuBootInfo) -> i32,
+    /// The services of sabinokaku, which stay valid for the lifetime of the process, or null.
+    pub services: *const SabinokakuServices,
+}
+
+/// Where and how the runtime is being booted, which is passed on to the entry points as a [`SabinokakuBootInfo`].
+#[derive(Debug, Clone)]
+pub struct BootContext {
+    pub trigger: BootTrigger,
+    pub config_path: Option<PathBuf>,
+    pub module_path: Option<PathBuf>,
+    /// The `VkInstance`, and the `VkDevice` if booted from `vkCreateDevice`, for Vulkan triggers.
+    pub trigger_data: Vec<u64>,
+    pub services: Option<&'static SabinokakuServices>,
+}
+
+impl BootContext {
+    pub fn new(trigger: BootTrigger) -> BootContext {
+        BootContext { trigger, config_path: None, module_path: None, trigger_data: Vec::new(), services: None }
+    }
+}
+
+/// Owns a [`SabinokakuBootInfo`] and everything it points to.
+///
+/// Both sabinokaku and managed code hold a reference to the buffer. Managed code gives up its reference by
+/// calling [`free_args`], and the buffer is freed once every reference is released, so that it stays valid until
+/// every entry point has returned even if one of them frees it early.
+#[repr(C)]
+struct BootInfoBuffer {
+    /// Must come first, so that a pointer to the info is also a pointer to the buffer.
+    info: SabinokakuBootInfo,
+    references: usize,
+    freed_by_managed: bool,
+    _strings: Vec<PdCString>,
+    _pointers: Vec<Vec<*const PdChar>>,
+    _trigger_data: Vec<u64>,
+}
+
+/// The addresses of every [`BootInfoBuffer`] that is not yet freed, so that [`free_args`] only ever frees one of
+/// them. Reference counts are only changed while it is locked.
+fn live_buffers() -> MutexGuard<'static, Vec<usize>> {
+    static LIVE_BUFFERS: AtomicPtr<Mutex<Vec<usize>>> = AtomicPtr::new(std::ptr::null_mut());
+
+    let mut live = LIVE_BUFFERS.load(Ordering::Acquire);
+    if live.is_null() {
+        let new = Box::into_raw(Box::new(Mutex::new(Vec::new())));
+        live = match LIVE_BUFFERS.compare_exchange(std::ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => new,
+            Err(existing) => {
+                drop(unsafe { Box::from_raw(new) });
+                existing
+            }
+        };
+    }
+    unsafe { &*live }.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The reference of sabinokaku to a boot info, released on drop.
+pub struct BootInfo {
+    buffer: *mut BootInfoBuffer,
+}
+
+impl BootInfo {
+    /// Fills in the boot info for `context` and `arguments`.
+    pub fn new(context: &BootContext, arguments: Vec<PdCString>) -> Result<BootInfo, Box<dyn Error>> {
+        let host_args = std::env::args_os()
+            .map(PdCString::from_os_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        let config_path = context.config_path.as_ref().map(PdCString::from_os_str).transpose()?;
+        let module_path = context.module_path.as_ref().map(PdCString::from_os_str).transpose()?;
+
+        let host_argv: Vec<*const PdChar> = host_args.iter().map(|s| s.as_ptr()).collect();
+        let argv: Vec<*const PdChar> = arguments.iter().map(|s| s.as_ptr()).collect();
+        let trigger_data = context.trigger_data.clone();
+
+        // Moving the strings and vectors into the buffer does not move the memory they point to.
+        let info = SabinokakuBootInfo {
+            version: BOOT_INFO_VERSION,
+            size: size_of::<SabinokakuBootInfo>() as u32,
+            trigger: context.trigger,
+            host_pid: std::process::id(),
+            config_path: config_path.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            module_path: module_path.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            host_argc: host_argv.len(),
+            host_argv: non_empty_ptr(&host_argv),
+            argc: argv.len(),
+            argv: non_empty_ptr(&argv),
+            trigger_data: non_empty_ptr(&trigger_data) as *const c_void,
+            trigger_data_size: size_of_val(trigger_data.as_slice()),
+            free_args,
+            services: context.services.map_or(std::ptr::null(), |services| services as *const SabinokakuServices),
+        };
+
+        let strings = host_args.into_iter().chain(arguments).chain(config_path).chain(module_path).collect();
+        let buffer = Box::into_raw(Box::new(BootInfoBuffer {
+            info,
+            references: 1,
+            freed_by_managed: false,
+            _strings: strings,
+            _pointers: vec![host_argv, argv],
+            _trigger_data: trigger_data,
+        }));
+        live_buffers().push(buffer as usize);
+        Ok(BootInfo { buffer })
+    }
+
+    /// Hands a reference to managed code, which must be done before the boot info is first passed to it.
+    pub fn share(&self) {
+        let _live = live_buffers();
+        unsafe { (*self.buffer).references += 1 };
+    }
+
+    pub fn as_ptr(&self) -> *const SabinokakuBootInfo {
+        self.buffer as *const SabinokakuBootInfo
+    }
+
+    pub fn size(&self) -> usize {
+        size_of::<SabinokakuBootInfo>()
+    }
+}
+
+impl Drop for BootInfo {
+    fn drop(&mut self) {
+        release(&mut live_buffers(), self.buffer)
+    }
+}
+
+/// Gives up the reference of managed code to a boot info, returning `1` if it was released, or `0` if `info`
+/// is null, was already freed, or is not a boot info at all. The boot info must not be read once it is freed,
+/// but freeing it again is safe.
+///
+/// # Safety
+/// `info` may be any pointer, but only pointers passed to an entry point are ever freed.
+pub unsafe extern "C" fn free_args(info: *const SabinokakuBootInfo) -> i32 {
+    let mut live = live_buffers();
+    let buffer = info as *mut BootInfoBuffer;
+    if !live.contains(&(buffer as usize)) || (*buffer).freed_by_managed {
+        return 0;
+    }
+    (*buffer).freed_by_managed = true;
+    release(&mut live, buffer);
+    1
+}
+
+/// Releases a reference to `buffer`, freeing it if it was the last. `live` must be the locked [`live_buffers`].
+fn release(live: &mut Vec<usize>, buffer: *mut BootInfoBuffer) {
+    unsafe {
+        (*buffer).references -= 1;
+        if (*buffer).references == 0 {
+            live.retain(|&b| b != buffer as usize);
+            drop(Box::from_raw(buffer));
+        }
+    }
+}
+
+/// A pointer to the elements of `slice`, or null if it is empty.
+fn non_empty_ptr<T>(slice: &[T]) -> *const T {
+    if slice.is_empty() {
+        std::ptr::null()
+    } else {
+        slice.as_ptr()
+    }
+}
+
+impl From<VulkanEntryPoint> for BootTrigger {
+    fn from(entry: VulkanEntryPoint) -> BootTrigger {
+        match entry {
+            VulkanEntryPoint::CreateInstance => BootTrigger::VkCreateInstance,
+            VulkanEntryPoint::CreateDevice => BootTrigger::VkCreateDevice,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::boot::{free_args, BootContext, BootInfo, BootTrigger, SabinokakuBootInfo};
+
+    #[test]
+    fn test_free_args_twice() {
+        let info = BootInfo::new(&BootContext::new(BootTrigger::Api), Vec::new()).unwrap();
+        let ptr = info.as_ptr();
+        info.share();
+        unsafe {
+            assert_eq!(free_args(ptr), 1);
+            assert_eq!(free_args(ptr), 0);
+        }
+        drop(info);
+        // Every reference is released, so the boot info is freed, but freeing it again is still safe.
+        unsafe {
+            assert_eq!(free_args(ptr), 0);
+            assert_eq!(free_args(std::ptr::null()), 0);
+        }
+    }
+
+    #[test]
+    fn test_free_args_after_release() {
+        let info = BootInfo::new(&BootContext::new(BootTrigger::Api), Vec::new()).unwrap();
+        let ptr = info.as_ptr();
+        info.share();
+        drop(info);
+        unsafe {
+            assert_eq!((*ptr).trigger, BootTrigger::Api);
+            assert_eq!(free_args(ptr), 1);
+            assert_eq!(free_args(ptr), 0);
+        }
+    }
+
+    #[test]
+    fn test_free_args_foreign() {
+        let info = BootInfo::new(&BootContext::new(BootTrigger::Api), Vec::new()).unwrap();
+        info.share();
+        let foreign = [0u8; 256];
+        unsafe {
+            assert_eq!(free_args(foreign.as_ptr() as *const SabinokakuBootInfo), 0);
+            assert_eq!(free_args(info.as_ptr().cast::<u8>().add(8) as *const SabinokakuBootInfo), 0);
+            assert_eq!(free_args(info.as_ptr()), 1);
+        }
+    }
+}
@@ -6,22 +6,75 @@ use std::path::{Path, PathBuf};
 use std::str::{FromStr, Lines};
 
 use netcorehost::pdcstring::PdCString;
-use crate::config::AdditionalParameter::{DotNetRoot, EnvironmentVariable, Hostfxr, Vulkan};
+use crate::services::LogLevel;
+use crate::config::AdditionalParameter::{Application, Argument, Boot, Conditional, Custom, DotNetRoot, Entry, EntryOverride,
+                                         EnvironmentAppend, EnvironmentDefault, EnvironmentPrepend, EnvironmentUnset,
+                                         EnvironmentVariable, ForwardArguments, Hostfxr, Include, Log, LogTo, OnExit, OnFailure, OnReturn,
+                                         Report, RestoreEnvironment, RuntimeProperty, Signature, Skip, Strict, Vulkan};
 
-#[derive(Debug, Clone)]
+mod condition;
+mod environment;
+mod expand;
+mod include;
+mod registry;
+mod search;
+mod structured;
+mod writer;
+
+pub use condition::{Condition, ConditionalSection, HostContext};
+pub use environment::{EnvironmentAction, EnvironmentSnapshot};
+pub use expand::ExpansionContext;
+pub use registry::{Registry, RegistryMapping};
+pub use search::{config_candidates, registry_only, user_config_dir, CONFIG_ENV_VAR, MAX_PARENT_DEPTH};
+use expand::Expander;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LoadConfig {
     pub runtime_config: PdCString,
     pub type_name: PdCString,
     pub entry_method: PdCString,
     pub entry_assembly: PdCString,
     pub additional_params: Vec<AdditionalParameter>,
+    pub warnings: Vec<ConfigDiagnostic>,
 }
 
 #[derive(Debug)]
 pub enum ConfigError {
     MissingOrInvalidConfigMagic(Option<String>),
-    InvalidConfig,
-    MissingConfig
+    InvalidConfig(ConfigDiagnostic),
+    StrictModeViolation(Vec<ConfigDiagnostic>),
+    Unrepresentable(String),
+    /// The chain of files that include each other, where the last file is the first one included again.
+    IncludeCycle(Vec<PathBuf>),
+    MissingInclude(PathBuf),
+    /// Every path that was tried.
+    MissingConfig(Vec<PathBuf>),
+    /// A registry exists, but maps no configuration to the host executable, and is the only source meant for it,
+    /// so there is nothing to do. Holds every other path that was tried.
+    Unmapped(Vec<PathBuf>)
+}
+
+/// The line-based formats a [`LoadConfig`] can be written back out as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Long,
+    Short
+}
+
+/// The offending part of a line, and the reason it is a problem, before it is located as a [`ConfigDiagnostic`].
+type Problem<'l> = (&'l str, String);
+
+/// A problem found at a specific location of a configuration file.
+///
+/// Lines and columns are 1-based, and `text` is the full text of the offending line. `file` is only known
+/// for configurations read with [`LoadConfig::load`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDiagnostic {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub reason: String,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -30,76 +83,550 @@ pub enum VulkanEntryPoint {
     CreateInstance
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AdditionalParameter {
     EnvironmentVariable(OsString, OsString),
+    EnvironmentUnset(OsString),
+    /// Sets the variable only if it is not already set.
+    EnvironmentDefault(OsString, OsString),
+    /// Adds to the start of a list of paths, separated by the platform path separator.
+    EnvironmentPrepend(OsString, OsString),
+    /// Adds to the end of a list of paths, separated by the platform path separator.
+    EnvironmentAppend(OsString, OsString),
+    /// Restores the variable, or every variable if `None`, once the runtime has booted.
+    RestoreEnvironment(Option<OsString>),
     Hostfxr(PathBuf),
     DotNetRoot(PathBuf),
-    Vulkan(VulkanInitParams)
+    Vulkan(VulkanInitParams),
+    Strict,
+    Conditional(ConditionalSection),
+    /// An entry point called in the same runtime as the one in the preamble.
+    Entry(EntryPoint),
+    /// Only valid inside a [`ConditionalSection`], replaces the entry point of the configuration.
+    EntryOverride(EntryPoint),
+    /// The signature of the entry point of the preamble, if it is not [`EntrySignature::Default`].
+    Signature(EntrySignature),
+    /// Only valid inside a [`ConditionalSection`], do not boot the runtime at all.
+    Skip,
+    /// A string passed to the entry points, when they are not passed anything else.
+    Argument(OsString),
+    /// Passes the arguments of the host process after those of any `arg` directive.
+    ForwardArguments,
+    /// Set by the `kaku_x` preamble, the entry assembly is run as an application through its `Main`.
+    Application,
+    /// What to do if the runtime can not be booted, or an entry point can not be called.
+    OnFailure(FailurePolicy),
+    /// What to do if an entry point returns non-zero.
+    OnReturn(FailurePolicy),
+    /// A property set on the runtime before any entry point is loaded, as if it were in `runtimeconfig.json`.
+    RuntimeProperty(OsString, OsString),
+    /// A file of directives to merge in place, which is resolved and removed by [`LoadConfig::load`].
+    Include(PathBuf),
+    /// An `x-NAME` directive that sabinokaku ignores, for managed code to read. The name excludes the `x-`.
+    Custom(String, OsString),
+    /// The most verbose level that is logged, or `None` to log nothing.
+    Log(Option<LogLevel>),
+    /// Where log messages go, in addition to any other `LogTo`.
+    LogTo(LogSink),
+    /// Where to write a JSON report of booting the runtime, see [`BootReport`](crate::report::BootReport).
+    Report(PathBuf),
+    /// How booting is ordered with the `main` of the host process when preloaded.
+    Boot(BootMode),
+    /// Whether the host process waits for the boot thread when it exits.
+    OnExit(ExitPolicy),
+}
+
+/// A managed entry point, the assembly to load and the method to call in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryPoint {
+    pub assembly: PdCString,
+    pub type_name: PdCString,
+    pub method: PdCString,
+    /// Entry points are called in ascending order, where the entry point of the preamble is always 0.
+    pub order: i32,
+    /// Whether the remaining entry points are still called if this one fails or returns non-zero.
+    pub continue_on_failure: bool,
+    pub signature: EntrySignature,
+}
+
+/// How the function pointer of an entry point is resolved.
+///
+/// Whatever the signature, the entry point is called natively as `int32_t (*)(void *args, size_t length)`,
+/// so the managed method must be compatible with it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntrySignature {
+    /// `public delegate int ComponentEntryPoint(IntPtr args, int sizeBytes)`, marshalled by the runtime.
+    Default,
+    /// A method marked `[UnmanagedCallersOnly]`, called without any marshalling.
+    UnmanagedCallersOnly,
+    /// A method matching the signature of the assembly qualified delegate type.
+    Delegate(PdCString),
+}
+
+/// What to do when booting fails, or an entry point returns non-zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    Ignore,
+    /// Reports the failure, and lets the host process carry on. This is the default.
+    Log,
+    /// Reports the failure, and exits the host process with the code, or the return code of the entry point
+    /// if none is given.
+    Exit(Option<i32>),
+    /// Reports the failure, and aborts the host process.
+    Abort,
 }
 
-#[derive(Debug, Clone)]
+/// How booting is ordered with the `main` of the host process, when `libkaku.so` is preloaded. Other triggers
+/// always boot the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMode {
+    /// Boots on a thread of its own while `main` runs. This is the default.
+    ///
+    /// Only the search for the configuration, and [`LoadConfig::probe_boot_order`], happen before `main`. The
+    /// configuration is loaded on the boot thread.
+    Async,
+    /// Boots on a thread of its own, and calls `main` once every entry point has returned.
+    Blocking,
+    /// Boots on a thread of its own, and calls `main` once managed code signals it is ready, every entry point has
+    /// returned, or the timeout in milliseconds has passed, [`DEFAULT_READY_TIMEOUT_MS`] unless given.
+    Ready(Option<u32>),
+    /// Boots on the main thread, and calls `main` once every entry point has returned.
+    MainThread,
+}
+
+/// How long [`BootMode::Ready`] waits for managed code unless it is given a timeout.
+pub const DEFAULT_READY_TIMEOUT_MS: u32 = 5000;
+
+/// What happens to the thread that boots the runtime when the host process exits, when `libkaku.so` is preloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitPolicy {
+    /// The thread is killed along with the host process. This is the default.
+    Detach,
+    /// The host process waits for every entry point to return before it exits, for at most the timeout in
+    /// milliseconds, [`DEFAULT_EXIT_TIMEOUT_MS`] unless given.
+    Wait(Option<u32>),
+}
+
+/// How long [`ExitPolicy::Wait`] waits for the boot thread unless it is given a timeout.
+pub const DEFAULT_EXIT_TIMEOUT_MS: u32 = 5000;
+
+/// Where log messages go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogSink {
+    Stderr,
+    /// Appended to the file, along with the time and process id.
+    File(PathBuf),
+    /// The system logger, which is journald on most Linux distributions. Not supported on Windows.
+    Syslog,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct VulkanInitParams {
     pub loader_version: u32,
     pub entry: VulkanEntryPoint
 }
 
+impl Display for VulkanEntryPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VulkanEntryPoint::CreateDevice => write!(f, "CreateDevice"),
+            VulkanEntryPoint::CreateInstance => write!(f, "CreateInstance"),
+        }
+    }
+}
+
+impl Display for EntryPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}!{}", self.type_name.to_string_lossy(), self.method.to_string_lossy())
+    }
+}
+
+impl Display for FailurePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailurePolicy::Ignore => write!(f, "ignore"),
+            FailurePolicy::Log => write!(f, "log"),
+            FailurePolicy::Exit(None) => write!(f, "exit"),
+            FailurePolicy::Exit(Some(code)) => write!(f, "exit {}", code),
+            FailurePolicy::Abort => write!(f, "abort"),
+        }
+    }
+}
+
+impl FromStr for FailurePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(' ') {
+            Some(("exit", code)) => code.parse().map(|code| FailurePolicy::Exit(Some(code))).map_err(|_| ()),
+            Some(_) => Err(()),
+            None => match s {
+                "ignore" => Ok(FailurePolicy::Ignore),
+                "log" => Ok(FailurePolicy::Log),
+                "exit" => Ok(FailurePolicy::Exit(None)),
+                "abort" => Ok(FailurePolicy::Abort),
+                _ => Err(())
+            }
+        }
+    }
+}
+
+impl Display for BootMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BootMode::Async => write!(f, "async"),
+            BootMode::Blocking => write!(f, "blocking"),
+            BootMode::Ready(None) => write!(f, "ready"),
+            BootMode::Ready(Some(timeout)) => write!(f, "ready {}", timeout),
+            BootMode::MainThread => write!(f, "main-thread"),
+        }
+    }
+}
+
+impl FromStr for BootMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(' ') {
+            Some(("ready", timeout)) => timeout.parse().map(|timeout| BootMode::Ready(Some(timeout))).map_err(|_| ()),
+            Some(_) => Err(()),
+            None => match s {
+                "async" => Ok(BootMode::Async),
+                "blocking" => Ok(BootMode::Blocking),
+                "ready" => Ok(BootMode::Ready(None)),
+                "main-thread" => Ok(BootMode::MainThread),
+                _ => Err(())
+            }
+        }
+    }
+}
+
+impl Display for ExitPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitPolicy::Detach => write!(f, "detach"),
+            ExitPolicy::Wait(None) => write!(f, "wait"),
+            ExitPolicy::Wait(Some(timeout)) => write!(f, "wait {}", timeout),
+        }
+    }
+}
+
+impl FromStr for ExitPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(' ') {
+            Some(("wait", timeout)) => timeout.parse().map(|timeout| ExitPolicy::Wait(Some(timeout))).map_err(|_| ()),
+            Some(_) => Err(()),
+            None => match s {
+                "detach" => Ok(ExitPolicy::Detach),
+                "wait" => Ok(ExitPolicy::Wait(None)),
+                _ => Err(())
+            }
+        }
+    }
+}
+
+impl FromStr for VulkanEntryPoint {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "CreateInstance" | "vkCreateInstance" => Ok(VulkanEntryPoint::CreateInstance),
+            "CreateDevice" | "vkCreateDevice" => Ok(VulkanEntryPoint::CreateDevice),
+            _ => Err(())
+        }
+    }
+}
+
 pub trait ConfigSearchPath {
     fn get_module_path() -> Option<PathBuf>;
 
+    fn expansion_context() -> ExpansionContext {
+        ExpansionContext::for_module(Self::get_module_path())
+    }
+
+    /// The host process that `when` sections are matched against.
+    fn host_context() -> HostContext {
+        HostContext::current()
+    }
+
+    /// Every path a configuration file is looked for at, see [`config_candidates`].
+    fn config_candidates() -> Vec<PathBuf> {
+        config_candidates(Self::get_module_path().as_deref(), current_exe().ok().as_deref(), user_config_dir().as_deref(),
+                          expand::home_dir().map(PathBuf::from).as_deref())
+    }
+
+    /// Registries that are consulted before searching, see [`Registry::default_paths`].
+    fn registry_paths() -> Vec<PathBuf> {
+        Registry::default_paths()
+    }
+
+    /// Finds the configuration file, or lists every path that was tried.
+    ///
+    /// If [`CONFIG_ENV_VAR`] is set, the file it points at is the only one tried. Otherwise, a configuration
+    /// mapped to the host executable in a registry is the only one tried. If a registry exists but nothing
+    /// is mapped, no configuration is found, and the registry is the only source meant for the host process, see
+    /// [`registry_only`], this is [`ConfigError::Unmapped`] rather than an error.
     fn search_for_config() -> Result<PathBuf, Box<dyn Error>> {
-        let module_parent = Self::get_module_path();
-        if let Some(Some(mut kaku_path)) = module_parent.map(|s| s.parent().map(PathBuf::from)) {
-            kaku_path.push("kaku.co");
-            if kaku_path.exists() {
-                return Ok(kaku_path);
+        let explicit = |path: PathBuf| -> Result<PathBuf, Box<dyn Error>> {
+            if path.is_file() {
+                Ok(path)
+            } else {
+                Err(Box::new(ConfigError::MissingConfig(vec![path])))
             }
+        };
+
+        if let Some(path) = std::env::var_os(CONFIG_ENV_VAR).filter(|path| !path.is_empty()) {
+            return explicit(PathBuf::from(path));
         }
 
-        if let Ok(Some(mut kaku_path)) = current_exe()
-            .map(|s| s.parent().map(PathBuf::from)) {
-            kaku_path.push("kaku.co");
-            if kaku_path.exists() {
-                return Ok(kaku_path);
+        let registry = Registry::load(&Self::registry_paths());
+        let exe = current_exe().and_then(|exe| exe.canonicalize()).ok();
+        if let (Some(registry), Some(exe)) = (&registry, &exe) {
+            if let Some(path) = registry.lookup(exe) {
+                return explicit(PathBuf::from(path));
             }
         }
 
-        Err(Box::new(ConfigError::MissingConfig))
+        let candidates = Self::config_candidates();
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None if registry.is_some() && registry_only(Self::get_module_path().as_deref(), exe.as_deref()) => {
+                Err(Box::new(ConfigError::Unmapped(candidates)))
+            }
+            None => Err(Box::new(ConfigError::MissingConfig(candidates)))
+        }
     }
 }
 
 impl Display for ConfigError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            ConfigError::MissingOrInvalidConfigMagic(s) => write!(f, "Configuration file magic number is missing, should be kaku_l or kaku_s, was {:?}.", s),
-            ConfigError::InvalidConfig => write!(f, "Configuration file is malformed."),
-            ConfigError::MissingConfig => write!(f, "kaku.co configuration file not found."),
+            ConfigError::MissingOrInvalidConfigMagic(s) => write!(f, "Configuration file magic number is missing, should be kaku_l, kaku_s, kaku_t or kaku_x, was {:?}.", s),
+            ConfigError::InvalidConfig(diagnostic) => write!(f, "Configuration file is malformed at {}.", diagnostic),
+            ConfigError::StrictModeViolation(warnings) => {
+                write!(f, "Configuration file has {} warning(s) in strict mode", warnings.len())?;
+                for warning in warnings {
+                    write!(f, "; {}", warning)?;
+                }
+                write!(f, ".")
+            }
+            ConfigError::Unrepresentable(reason) => write!(f, "Configuration can not be written: {}.", reason),
+            ConfigError::IncludeCycle(chain) => {
+                write!(f, "Configuration files include each other in a cycle: ")?;
+                for (i, path) in chain.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { "" } else { " -> " }, path.display())?;
+                }
+                write!(f, ".")
+            }
+            ConfigError::MissingInclude(path) => write!(f, "Included configuration file {} not found.", path.display()),
+            ConfigError::MissingConfig(tried) => {
+                write!(f, "kaku.co configuration file not found")?;
+                for (i, path) in tried.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { ", tried " } else { ", " }, path.display())?;
+                }
+                write!(f, ".")
+            }
+            ConfigError::Unmapped(_) => write!(f, "No configuration is mapped to this executable."),
         }
     }
 }
 
 impl Error for ConfigError {}
 
+impl ConfigDiagnostic {
+    /// Creates a diagnostic pointing at `token`, which should be a subslice of `text`.
+    /// Tokens that are not part of `text`, such as a missing argument, point past the end of the line.
+    pub fn new(line: usize, text: &str, token: &str, reason: impl Into<String>) -> ConfigDiagnostic {
+        let offset = (token.as_ptr() as usize).checked_sub(text.as_ptr() as usize)
+            .filter(|offset| offset + token.len() <= text.len())
+            .unwrap_or(text.len());
+        ConfigDiagnostic {
+            file: None,
+            line,
+            column: text[..offset].chars().count() + 1,
+            text: text.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}, ", file.display())?;
+        }
+        write!(f, "line {}, column {}: {} in {:?}", self.line, self.column, self.reason, self.text)
+    }
+}
+
+impl AdditionalParameter {
+    /// Whether the directive may only be given once. A file that gives it again is warned about, and the second is
+    /// ignored. Between files, the one merged last wins, see [`LoadConfig::load`].
+    pub fn is_single_valued(&self) -> bool {
+        matches!(self, Hostfxr(_) | DotNetRoot(_) | Vulkan(_) | Signature(_) | OnFailure(_) | OnReturn(_) | Log(_)
+            | Report(_) | Boot(_) | OnExit(_))
+    }
+}
+
 impl LoadConfig {
     pub fn new(runtime_config: PdCString, entry_assembly: PdCString, type_name: PdCString, entry_method: PdCString,
                additional_params: Vec<AdditionalParameter>) -> LoadConfig {
-        LoadConfig { runtime_config, type_name, entry_method, entry_assembly, additional_params }
+        LoadConfig { runtime_config, type_name, entry_method, entry_assembly, additional_params, warnings: Vec::new() }
     }
 
     pub fn try_parse(root: PathBuf, input: &dyn AsRef<str>) -> Result<LoadConfig, Box<dyn Error>> {
-        let mut input = input.as_ref();
+        LoadConfig::try_parse_with(root, input, &ExpansionContext::default())
+    }
+
+    /// Parses a configuration, expanding `${MODULE_DIR}` and `${EXE_DIR}` with the given `context`.
+    ///
+    /// `include` directives are kept as they are, use [`LoadConfig::load`] to resolve them.
+    pub fn try_parse_with(root: PathBuf, input: &dyn AsRef<str>, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+        LoadConfig::parse_unchecked(root, input.as_ref(), context)?.check_strict()
+    }
+
+    fn parse_unchecked(root: PathBuf, input: &str, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+        let mut input = input;
 
         // deal with BOM.
         if input.starts_with("\u{feff}") {
             input = &input.trim_start_matches("\u{feff}");
         }
 
+        // kaku.toml is always structured, regardless of whether it carries the magic comment.
+        if root.extension() == Some(OsStr::new("toml")) {
+            return structured::parse_structured(root, input, context);
+        }
+
         let mut lines = input.lines();
         match lines.next() {
-            Some("kaku_s") => LoadConfig::parse_short(root, lines),
-            Some("kaku_l") => LoadConfig::parse_long(root, lines),
-            x => Err(Box::new(ConfigError::MissingOrInvalidConfigMagic(x.map(String::from))))
+            Some("kaku_s") => LoadConfig::parse_short(root, lines, context),
+            Some("kaku_l") => LoadConfig::parse_long(root, lines, context),
+            Some("kaku_x") => LoadConfig::parse_app(root, lines, context),
+            Some(magic) if structured::is_structured_magic(magic) => structured::parse_structured(root, input, context),
+            x => Err(Box::new(ConfigError::MissingOrInvalidConfigMagic(x.map(String::from))).into())
+        }
+    }
+
+    /// In strict mode, any warning is promoted to a hard failure.
+    fn check_strict(self) -> Result<LoadConfig, Box<dyn Error>> {
+        if self.is_strict() && !self.warnings.is_empty() {
+            return Err(Box::new(ConfigError::StrictModeViolation(self.warnings)));
+        }
+        Ok(self)
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.additional_params.iter().any(|f| matches!(f, AdditionalParameter::Strict))
+    }
+
+    /// The most verbose level that is logged, or `None` to log nothing, which is the default.
+    pub fn log_level(&self) -> Option<LogLevel> {
+        self.additional_params.iter()
+            .find_map(|f| match f {
+                AdditionalParameter::Log(level) => Some(*level),
+                _ => None
+            })
+            .flatten()
+    }
+
+    /// Where to write the boot report, if anywhere.
+    pub fn report_path(&self) -> Option<&Path> {
+        self.additional_params.iter()
+            .find_map(|f| match f {
+                AdditionalParameter::Report(p) => Some(p.as_path()),
+                _ => None
+            })
+    }
+
+    /// Where log messages go, only [`LogSink::Stderr`] unless given.
+    pub fn log_sinks(&self) -> Vec<LogSink> {
+        let sinks: Vec<LogSink> = self.additional_params.iter()
+            .filter_map(|f| match f {
+                AdditionalParameter::LogTo(sink) => Some(sink.clone()),
+                _ => None
+            })
+            .collect();
+        if sinks.is_empty() {
+            vec![LogSink::Stderr]
+        } else {
+            sinks
+        }
+    }
+
+    /// The policy for failing to boot the runtime or call an entry point, [`FailurePolicy::Log`] unless given.
+    pub fn on_failure(&self) -> FailurePolicy {
+        self.additional_params.iter()
+            .find_map(|f| match f {
+                AdditionalParameter::OnFailure(policy) => Some(*policy),
+                _ => None
+            })
+            .unwrap_or(FailurePolicy::Log)
+    }
+
+    /// The policy for entry points returning non-zero, [`FailurePolicy::Log`] unless given.
+    pub fn on_return(&self) -> FailurePolicy {
+        self.additional_params.iter()
+            .find_map(|f| match f {
+                AdditionalParameter::OnReturn(policy) => Some(*policy),
+                _ => None
+            })
+            .unwrap_or(FailurePolicy::Log)
+    }
+
+    /// How booting is ordered with `main` when preloaded, [`BootMode::Async`] unless given.
+    pub fn boot_mode(&self) -> BootMode {
+        self.additional_params.iter()
+            .find_map(|f| match f {
+                AdditionalParameter::Boot(mode) => Some(*mode),
+                _ => None
+            })
+            .unwrap_or(BootMode::Async)
+    }
+
+    /// Whether the host process waits for the boot thread when it exits, [`ExitPolicy::Detach`] unless given.
+    pub fn on_exit(&self) -> ExitPolicy {
+        self.additional_params.iter()
+            .find_map(|f| match f {
+                AdditionalParameter::OnExit(policy) => Some(*policy),
+                _ => None
+            })
+            .unwrap_or(ExitPolicy::Detach)
+    }
+
+    /// Reads only the `boot` and `on-exit` directives of the configuration file at `path`, so that booting can be
+    /// ordered with `main` before the configuration is loaded. Nothing else is parsed, and includes are not
+    /// resolved. Directives that are missing or invalid are the defaults, and errors are left for loading to report.
+    pub fn probe_boot_order(path: &Path) -> (BootMode, ExitPolicy) {
+        let (mut mode, mut on_exit) = (None, None);
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        let text = text.trim_start_matches('\u{feff}');
+        let mut lines = text.lines();
+        let magic = lines.next().unwrap_or_default();
+
+        if path.extension() == Some(OsStr::new("toml")) || structured::is_structured_magic(magic) {
+            if let Ok(toml::Value::Table(document)) = text.parse::<toml::Value>() {
+                mode = document.get("boot").and_then(toml::Value::as_str).and_then(|mode| mode.parse().ok());
+                on_exit = document.get("on_exit").and_then(toml::Value::as_str).and_then(|policy| policy.parse().ok());
+            }
+        } else {
+            let preamble = if magic == "kaku_l" { 4 } else { 1 };
+            // As when parsing, the first valid directive wins, and `when` blocks can not hold either.
+            let mut in_section = false;
+            for line in lines.skip(preamble) {
+                match line.split_once(' ').unwrap_or((line, "")) {
+                    ("when", _) => in_section = true,
+                    ("end", _) => in_section = false,
+                    _ if in_section => {}
+                    ("boot", value) if mode.is_none() => mode = value.parse().ok(),
+                    ("on-exit", value) if on_exit.is_none() => on_exit = value.parse().ok(),
+                    _ => {}
+                }
+            }
         }
+        (mode.unwrap_or(BootMode::Async), on_exit.unwrap_or(ExitPolicy::Detach))
     }
 
     pub fn environment_variables(&self) -> impl Iterator<Item=(&OsStr, &OsStr)> {
@@ -111,6 +638,30 @@ impl LoadConfig {
         })
     }
 
+    /// The strings of `arg` directives, in the order they are passed.
+    pub fn arguments(&self) -> impl Iterator<Item=&OsStr> {
+        self.additional_params.iter().filter_map(|p| match p {
+            AdditionalParameter::Argument(arg) => Some(arg.as_os_str()),
+            _ => None
+        })
+    }
+
+    /// The runtime properties of `property` directives, in the order they are set.
+    pub fn runtime_properties(&self) -> impl Iterator<Item=(&OsStr, &OsStr)> {
+        self.additional_params.iter().filter_map(|p| match p {
+            AdditionalParameter::RuntimeProperty(k, v) => Some((k.as_os_str(), v.as_os_str())),
+            _ => None
+        })
+    }
+
+    /// The values of every custom `x-NAME` directive named `name`, without the `x-`, in order.
+    pub fn custom_directives<'a>(&'a self, name: &'a str) -> impl Iterator<Item=&'a OsStr> {
+        self.additional_params.iter().filter_map(move |p| match p {
+            AdditionalParameter::Custom(k, v) if k == name => Some(v.as_os_str()),
+            _ => None
+        })
+    }
+
     pub fn hostfxr_path(&self) -> Option<&Path> {
         self.additional_params.iter()
             .find_map(|f| match f {
@@ -127,6 +678,45 @@ impl LoadConfig {
             })
     }
 
+    /// Every entry point to call, in the order to call them. Entry points with the same order are called
+    /// in the order they appear in the configuration, after the entry point of the preamble.
+    pub fn entry_points(&self) -> Vec<EntryPoint> {
+        let mut entry_points = vec![EntryPoint {
+            assembly: self.entry_assembly.clone(),
+            type_name: self.type_name.clone(),
+            method: self.entry_method.clone(),
+            order: 0,
+            continue_on_failure: false,
+            signature: self.entry_signature(),
+        }];
+        entry_points.extend(self.additional_params.iter()
+            .filter_map(|f| match f {
+                AdditionalParameter::Entry(entry) => Some(entry.clone()),
+                _ => None
+            }));
+        entry_points.sort_by_key(|entry| entry.order);
+        entry_points
+    }
+
+    /// The signature of the entry point of the preamble.
+    pub fn entry_signature(&self) -> EntrySignature {
+        self.additional_params.iter()
+            .find_map(|f| match f {
+                AdditionalParameter::Signature(signature) => Some(signature.clone()),
+                _ => None
+            })
+            .unwrap_or(EntrySignature::Default)
+    }
+
+    /// Whether the entry assembly is an application, whose `Main` is run instead of calling the entry points.
+    pub fn is_application(&self) -> bool {
+        self.additional_params.iter().any(|p| matches!(p, AdditionalParameter::Application))
+    }
+
+    pub fn forwards_arguments(&self) -> bool {
+        self.additional_params.iter().any(|p| matches!(p, AdditionalParameter::ForwardArguments))
+    }
+
     pub fn vulkan(&self) -> Option<&VulkanInitParams> {
         self.additional_params.iter()
             .find_map(|f| match f {
@@ -135,41 +725,64 @@ impl LoadConfig {
             })
     }
 
-    fn parse_long(root: PathBuf, input: Lines) -> Result<LoadConfig, Box<dyn Error>> {
+    fn parse_long(root: PathBuf, input: Lines, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+        const PREAMBLE: [&str; 4] = [
+            "expected the path to runtimeconfig.json",
+            "expected the path to the entry-point assembly",
+            "expected the qualified name of the entry-point class",
+            "expected the name of the entry-point function",
+        ];
+
         let lines: Vec<&str> = input.collect();
-        if lines.len() < 4 {
-            return Err(Box::new(ConfigError::InvalidConfig));
+        if lines.len() < PREAMBLE.len() {
+            // The magic is on line 1, so the first missing preamble line is 2 past the last one read.
+            return Err(Box::new(ConfigError::InvalidConfig(
+                ConfigDiagnostic::new(lines.len() + 2, "", "", PREAMBLE[lines.len()]))));
         }
         let runtime_config = lines[0];
         let assembly_fname = lines[1];
         let entry_type = lines[2];
         let entry_fn = lines[3];
 
-        let root = root.parent().ok_or(ConfigError::MissingConfig)?;
+        let root = root.parent().ok_or_else(|| ConfigError::MissingConfig(vec![root.clone()]))?;
+        let expander = Expander { origin: root, context };
 
+        let (runtime_config, runtime_config_warnings) = expander.expand(runtime_config);
         let mut runtime_config_path = PathBuf::from(root);
         runtime_config_path.push(runtime_config);
 
+        let (assembly_fname, assembly_fname_warnings) = expander.expand(assembly_fname);
         let mut assembly_fname_path = PathBuf::from(root);
         assembly_fname_path.push(assembly_fname);
 
-        let additional = Self::parse_additional(&root, &lines[4..]);
+        let (additional, additional_warnings) = Self::parse_additional(&expander, &lines[4..], 6);
+        let warnings = runtime_config_warnings.into_iter()
+            .map(|(token, reason)| ConfigDiagnostic::new(2, lines[0], token, reason))
+            .chain(assembly_fname_warnings.into_iter()
+                .map(|(token, reason)| ConfigDiagnostic::new(3, lines[1], token, reason)))
+            .chain(additional_warnings)
+            .collect();
 
-        Ok(LoadConfig::new(
+        let mut config = LoadConfig::new(
             PdCString::from_os_str(runtime_config_path.as_os_str())?,
             PdCString::from_os_str(assembly_fname_path.as_os_str())?,
             PdCString::from_str(entry_type)?,
             PdCString::from_str(entry_fn)?,
             additional
-        ))
+        );
+        config.warnings = warnings;
+        Ok(config)
     }
 
-    fn parse_short(root: PathBuf, mut input: Lines) -> Result<LoadConfig, Box<dyn Error>> {
-        let line = input.next().ok_or(ConfigError::InvalidConfig)?;
-        let (asm, rest) = line.split_once("::").ok_or(ConfigError::InvalidConfig)?;
-        let (entry_cls, entry_fn) = rest.split_once("!").ok_or(ConfigError::InvalidConfig)?;
+    fn parse_short(root: PathBuf, mut input: Lines, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+        const EXPECTED_ENTRY: &str = "expected an entry point of the form `AssemblyName::QualifiedClassName!EntryFunction`";
+
+        let line = input.next()
+            .ok_or_else(|| ConfigError::InvalidConfig(ConfigDiagnostic::new(2, "", "", EXPECTED_ENTRY)))?;
+        let (asm, entry_cls, entry_fn) = split_short_entry(line)
+            .ok_or_else(|| ConfigError::InvalidConfig(ConfigDiagnostic::new(2, line, line, EXPECTED_ENTRY)))?;
 
-        let root = root.parent().ok_or(ConfigError::MissingConfig)?;
+        let root = root.parent().ok_or_else(|| ConfigError::MissingConfig(vec![root.clone()]))?;
 
         let mut runtime_config_path = PathBuf::from(root);
         runtime_config_path.push(&format!("{}.runtimeconfig.json", asm));
@@ -178,102 +791,522 @@ impl LoadConfig {
         assembly_fname_path.push(&format!("{}.dll", asm));
 
         let lines: Vec<&str> = input.collect();
-        let additional = Self::parse_additional(&root, &lines);
-        Ok(LoadConfig::new(
+        let (additional, warnings) = Self::parse_additional(&Expander { origin: root, context }, &lines, 3);
+        let mut config = LoadConfig::new(
             PdCString::from_os_str(runtime_config_path.as_os_str())?,
             PdCString::from_os_str(assembly_fname_path.as_os_str())?,
             PdCString::from_str(&format!("{}, {}", entry_cls, asm))?,
             PdCString::from_str(entry_fn)?,
             additional
-        ))
+        );
+        config.warnings = warnings;
+        Ok(config)
+    }
+
+    /// The preamble of `kaku_x` is only the path to the application assembly, relative to the configuration file.
+    /// As with `dotnet App.dll`, its runtime configuration sits next to it and shares its name.
+    fn parse_app(root: PathBuf, mut input: Lines, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+        const EXPECTED_APP: &str = "expected the path to the application assembly";
+
+        let line = input.next()
+            .filter(|line| !line.trim().is_empty())
+            .ok_or_else(|| ConfigError::InvalidConfig(ConfigDiagnostic::new(2, "", "", EXPECTED_APP)))?;
+
+        let root = root.parent().ok_or_else(|| ConfigError::MissingConfig(vec![root.clone()]))?;
+        let expander = Expander { origin: root, context };
+
+        let (app, app_warnings) = expander.expand(line);
+        let mut app_path = PathBuf::from(root);
+        app_path.push(app);
+        let app_name = app_path.file_stem()
+            .ok_or_else(|| ConfigError::InvalidConfig(ConfigDiagnostic::new(2, line, line, EXPECTED_APP)))?;
+        let runtime_config_path = app_path.with_extension("runtimeconfig.json");
+
+        let lines: Vec<&str> = input.collect();
+        let (mut additional, additional_warnings) = Self::parse_additional(&expander, &lines, 3);
+        additional.insert(0, Application);
+        let warnings = app_warnings.into_iter()
+            .map(|(token, reason)| ConfigDiagnostic::new(2, line, token, reason))
+            .chain(additional_warnings)
+            .collect();
+
+        let mut config = LoadConfig::new(
+            PdCString::from_os_str(runtime_config_path.as_os_str())?,
+            PdCString::from_os_str(app_path.as_os_str())?,
+            PdCString::from_os_str(app_name)?,
+            PdCString::from_str("Main")?,
+            additional
+        );
+        config.warnings = warnings;
+        Ok(config)
     }
 
-    fn parse_additional(root: &Path, input: &[&str]) -> Vec<AdditionalParameter> {
+    /// Parses the directives following the preamble, where `first_line` is the line number of `input[0]`.
+    ///
+    /// Malformed directives never fail the parse, they are skipped and reported as warnings instead.
+    fn parse_additional(expander: &Expander, input: &[&str], first_line: usize) -> (Vec<AdditionalParameter>, Vec<ConfigDiagnostic>) {
         let mut map = Vec::new();
+        let mut warnings = Vec::new();
+        // The `when` block currently being parsed, and the line it started on.
+        let mut section: Option<(ConditionalSection, usize)> = None;
 
-        for line in input {
-            match line.split_once(" ") {
-                Some(("env", env)) => {
-                    if let Some((k, v)) = env.split_once("=") {
-                        map.push(EnvironmentVariable(OsString::from(k), OsString::from(v)));
-                    }
-                }
-                Some(("hostfxr", hostfxr)) => {
-                    let mut buf = PathBuf::from(root);
-                    buf.push(hostfxr);
-                    map.push(Hostfxr(buf));
+        for (line_no, &line) in (first_line..).zip(input) {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (directive, value) = line.split_once(" ").unwrap_or((line, ""));
+            let problems = match (directive, section.as_mut()) {
+                ("when", Some(_)) => vec![(directive, String::from("`when` blocks can not be nested"))],
+                ("when", None) => {
+                    let (conditions, problems) = condition::parse_conditions(value);
+                    section = Some((ConditionalSection { conditions, params: Vec::new() }, line_no));
+                    problems
                 }
-                Some(("dotnetroot", dotnetroot)) => {
-                    let mut buf = PathBuf::from(root);
-                    buf.push(dotnetroot);
-                    map.push(DotNetRoot(buf));
+                ("end", Some(_)) => {
+                    map.extend(section.take().map(|(section, _)| Conditional(section)));
+                    vec![]
                 }
-                Some(("vulkan", vulkan)) => {
-                    if let Some((ld, entry)) = vulkan.split_once(" ") {
-                        if let Ok(ld) = ld.parse() {
-                            let entry_point = match entry {
-                                "CreateInstance" | "vkCreateInstance" => VulkanEntryPoint::CreateInstance,
-                                "CreateDevice" | "vkCreateDevice" => VulkanEntryPoint::CreateDevice,
-                                _ => continue
-                            };
-                            map.push(Vulkan(VulkanInitParams { loader_version: ld, entry: entry_point }))
-                        }
-                    }
+                ("end", None) => vec![(directive, String::from("`end` without a matching `when`"))],
+                ("entry", Some((section, _))) => Self::parse_entry_override(expander, line, &mut section.params),
+                ("env" | "env-unset" | "env-default" | "env-prepend" | "env-append" | "arg" | "skip", Some((section, _))) => {
+                    Self::parse_directive(expander, line, &mut section.params)
                 }
-                _ => {}
-            }
-        }
+                (_, Some(_)) => vec![(directive, format!("`{}` is not allowed inside `when`", directive))],
+                ("skip", None) => vec![(directive, format!("`{}` is only allowed inside `when`", directive))],
+                (_, None) => Self::parse_directive(expander, line, &mut map),
+            };
 
-        map
-    }
-}
+            warnings.extend(problems
+                .into_iter()
+                .map(|(token, reason)| ConfigDiagnostic::new(line_no, line, token, reason)));
+        }
 
-#[cfg(test)]
-mod tests {
-    use std::ffi::OsString;
-    use std::path::{Path, PathBuf};
-    use std::str::FromStr;
-    use netcorehost::pdcstr;
-    use crate::LoadConfig;
+        if let Some((_, line_no)) = section {
+            let line = input[line_no - first_line];
+            warnings.push(ConfigDiagnostic::new(line_no, line, line, "`when` block is missing its `end`, ignored"));
+        }
 
-    #[test]
-    fn test_parse_short() {
-        let kaku_co = "kaku_s
-Assembly::TestInject.EntryPoint!Main";
-        let config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
-        assert_eq!(config.runtime_config.as_ref(), pdcstr!("Assembly.runtimeconfig.json"));
-        assert_eq!(config.type_name.as_ref(), pdcstr!("TestInject.EntryPoint, Assembly"));
-        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("Assembly.dll"));
-        assert_eq!(config.entry_method.as_ref(), pdcstr!("Main"));
-        assert_eq!(config.additional_params.len(), 0);
+        (map, warnings)
     }
 
-    #[test]
-    fn test_parse_short_params() {
-        let kaku_co = "kaku_s
-Assembly::TestInject.EntryPoint!Main
-hostfxr HOSTFX
-env TESTENV=TEST
-env TESTENV2=TEST2
-dotnetroot DOTNETROOT
-";
-        let config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
-        assert_eq!(config.runtime_config.as_ref(), pdcstr!("Assembly.runtimeconfig.json"));
-        assert_eq!(config.type_name.as_ref(), pdcstr!("TestInject.EntryPoint, Assembly"));
-        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("Assembly.dll"));
-        assert_eq!(config.entry_method.as_ref(), pdcstr!("Main"));
-        assert_eq!(config.environment_variables().collect::<Vec<_>>(), vec![
-            (OsString::from_str("TESTENV").unwrap().as_os_str(), OsString::from_str("TEST").unwrap().as_os_str()),
-            (OsString::from_str("TESTENV2").unwrap().as_os_str(), OsString::from_str("TEST2").unwrap().as_os_str())
-        ]);
-        assert_eq!(config.hostfxr_path(), Some(Path::new("HOSTFX")));
-        assert_eq!(config.dotnetroot_path(), Some(Path::new("DOTNETROOT")));
-    }
+    /// Parses a single directive into `map`, returning any warnings as the offending part of `line` and a reason.
+    fn parse_directive<'l>(expander: &Expander, line: &'l str, map: &mut Vec<AdditionalParameter>) -> Vec<Problem<'l>> {
+        let (directive, value) = line.split_once(" ").unwrap_or((line, ""));
 
-    #[test]
-    fn test_parse_long() {
-        let kaku_co = "kaku_l
-Assembly.runtimeconfig.json
+        match directive {
+            "env" | "env-default" | "env-prepend" | "env-append" => {
+                if let Some((k, v)) = value.split_once("=") {
+                    let (v, warnings) = expander.expand(v);
+                    let k = OsString::from(k);
+                    map.push(match directive {
+                        "env-default" => EnvironmentDefault(k, v),
+                        "env-prepend" => EnvironmentPrepend(k, v),
+                        "env-append" => EnvironmentAppend(k, v),
+                        _ => EnvironmentVariable(k, v),
+                    });
+                    warnings
+                } else {
+                    vec![(value, format!("expected `KEY=VALUE` after `{}`", directive))]
+                }
+            }
+            "env-unset" => {
+                if value.is_empty() || value.contains(['=', ' ']) {
+                    vec![(value, String::from("expected a variable name after `env-unset`"))]
+                } else {
+                    map.push(EnvironmentUnset(OsString::from(value)));
+                    vec![]
+                }
+            }
+            "hostfxr" | "dotnetroot" if value.is_empty() => {
+                vec![(value, format!("expected a path after `{}`", directive))]
+            }
+            "hostfxr" if map.iter().any(|p| matches!(p, Hostfxr(_))) => {
+                vec![(directive, String::from("`hostfxr` specified twice, second ignored"))]
+            }
+            "hostfxr" => {
+                let (hostfxr, warnings) = expander.expand(value);
+                let mut buf = PathBuf::from(expander.origin);
+                buf.push(hostfxr);
+                map.push(Hostfxr(buf));
+                warnings
+            }
+            "dotnetroot" if map.iter().any(|p| matches!(p, DotNetRoot(_))) => {
+                vec![(directive, String::from("`dotnetroot` specified twice, second ignored"))]
+            }
+            "dotnetroot" => {
+                let (dotnetroot, warnings) = expander.expand(value);
+                let mut buf = PathBuf::from(expander.origin);
+                buf.push(dotnetroot);
+                map.push(DotNetRoot(buf));
+                warnings
+            }
+            "vulkan" if map.iter().any(|p| matches!(p, Vulkan(_))) => {
+                vec![(directive, String::from("`vulkan` specified twice, second ignored"))]
+            }
+            "vulkan" => {
+                let (ld, entry) = match value.split_once(" ") {
+                    Some(split) => split,
+                    None => return vec![(value, String::from("expected a loader version and an entry point after `vulkan`"))]
+                };
+                let ld = match ld.parse() {
+                    Ok(ld) => ld,
+                    Err(_) => return vec![(ld, format!("invalid Vulkan loader version `{}`", ld))]
+                };
+                match entry.parse() {
+                    Ok(entry_point) => {
+                        map.push(Vulkan(VulkanInitParams { loader_version: ld, entry: entry_point }));
+                        vec![]
+                    }
+                    Err(_) => vec![(entry, format!("unknown Vulkan entry point `{}`", entry))]
+                }
+            }
+            "entry" => match parse_entry(expander, value) {
+                Ok((entry, warnings)) => {
+                    map.push(Entry(entry));
+                    warnings
+                }
+                Err(warning) => vec![warning]
+            },
+            "on-failure" if map.iter().any(|p| matches!(p, OnFailure(_))) => {
+                vec![(directive, String::from("`on-failure` specified twice, second ignored"))]
+            }
+            "on-return" if map.iter().any(|p| matches!(p, OnReturn(_))) => {
+                vec![(directive, String::from("`on-return` specified twice, second ignored"))]
+            }
+            "on-failure" | "on-return" => match value.parse() {
+                Ok(policy) if directive == "on-failure" => {
+                    map.push(OnFailure(policy));
+                    vec![]
+                }
+                Ok(policy) => {
+                    map.push(OnReturn(policy));
+                    vec![]
+                }
+                Err(_) => vec![(value, format!("expected `ignore`, `log`, `exit [CODE]` or `abort` after `{}`", directive))]
+            },
+            "delegate" if value.is_empty() => {
+                vec![(value, String::from("expected `unmanaged`, or an assembly qualified delegate type after `delegate`"))]
+            }
+            "delegate" if map.iter().any(|p| matches!(p, Signature(_))) => {
+                vec![(directive, String::from("`delegate` specified twice, second ignored"))]
+            }
+            "delegate" => match parse_signature(value) {
+                Some(signature) => {
+                    map.push(Signature(signature));
+                    vec![]
+                }
+                None => vec![(value, format!("invalid delegate type `{}`", value))]
+            },
+            "skip" => {
+                if !map.contains(&Skip) {
+                    map.push(Skip);
+                }
+                if value.is_empty() {
+                    vec![]
+                } else {
+                    vec![(value, String::from("`skip` takes no arguments"))]
+                }
+            }
+            "property" => {
+                if let Some((k, v)) = value.split_once("=").filter(|(k, _)| !k.is_empty()) {
+                    let (v, warnings) = expander.expand(v);
+                    map.push(RuntimeProperty(OsString::from(k), v));
+                    warnings
+                } else {
+                    vec![(value, String::from("expected `NAME=VALUE` after `property`"))]
+                }
+            }
+            "arg" if value.is_empty() => {
+                vec![(value, String::from("expected a value after `arg`"))]
+            }
+            "arg" => {
+                let (arg, warnings) = expander.expand(value);
+                map.push(Argument(arg));
+                warnings
+            }
+            "restore-env" => {
+                if value.contains(['=', ' ']) {
+                    return vec![(value, String::from("expected a variable name, or nothing after `restore-env`"))];
+                }
+                let param = RestoreEnvironment(Some(OsString::from(value)).filter(|k| !k.is_empty()));
+                if !map.contains(&param) {
+                    map.push(param);
+                }
+                vec![]
+            }
+            "include" if value.is_empty() => {
+                vec![(value, String::from("expected a path after `include`"))]
+            }
+            "include" => {
+                let (include, warnings) = expander.expand(value);
+                let mut buf = PathBuf::from(expander.origin);
+                buf.push(include);
+                map.push(Include(buf));
+                warnings
+            }
+            "strict" => {
+                if !map.contains(&Strict) {
+                    map.push(Strict);
+                }
+                if value.is_empty() {
+                    vec![]
+                } else {
+                    vec![(value, String::from("`strict` takes no arguments"))]
+                }
+            }
+            "forward-args" => {
+                if !map.contains(&ForwardArguments) {
+                    map.push(ForwardArguments);
+                }
+                if value.is_empty() {
+                    vec![]
+                } else {
+                    vec![(value, String::from("`forward-args` takes no arguments"))]
+                }
+            }
+            "log" if map.iter().any(|p| matches!(p, Log(_))) => {
+                vec![(directive, String::from("`log` specified twice, second ignored"))]
+            }
+            "log" => match LogLevel::parse_filter(value) {
+                Some(level) => {
+                    map.push(Log(level));
+                    vec![]
+                }
+                None => vec![(value, String::from("expected `off`, `error`, `warn`, `info`, `debug` or `trace` after `log`"))]
+            },
+            "log-to" => {
+                let (sink, warnings) = match value.split_once(' ').unwrap_or((value, "")) {
+                    ("stderr", "") => (LogSink::Stderr, vec![]),
+                    ("syslog", "") => (LogSink::Syslog, vec![]),
+                    ("file", path) if !path.is_empty() => {
+                        let (path, warnings) = expander.expand(path);
+                        let mut buf = PathBuf::from(expander.origin);
+                        buf.push(path);
+                        (LogSink::File(buf), warnings)
+                    }
+                    _ => return vec![(value, String::from("expected `stderr`, `syslog` or `file PATH` after `log-to`"))]
+                };
+                let param = LogTo(sink);
+                if !map.contains(&param) {
+                    map.push(param);
+                }
+                warnings
+            }
+            "report" if value.is_empty() => {
+                vec![(value, String::from("expected a path after `report`"))]
+            }
+            "report" if map.iter().any(|p| matches!(p, Report(_))) => {
+                vec![(directive, String::from("`report` specified twice, second ignored"))]
+            }
+            "report" => {
+                let (report, warnings) = expander.expand(value);
+                let mut buf = PathBuf::from(expander.origin);
+                buf.push(report);
+                map.push(Report(buf));
+                warnings
+            }
+            "boot" if map.iter().any(|p| matches!(p, Boot(_))) => {
+                vec![(directive, String::from("`boot` specified twice, second ignored"))]
+            }
+            "boot" => match value.parse() {
+                Ok(mode) => {
+                    map.push(Boot(mode));
+                    vec![]
+                }
+                Err(_) => vec![(value, String::from("expected `async`, `blocking`, `ready [TIMEOUT_MS]` or `main-thread` after `boot`"))]
+            },
+            "on-exit" if map.iter().any(|p| matches!(p, OnExit(_))) => {
+                vec![(directive, String::from("`on-exit` specified twice, second ignored"))]
+            }
+            "on-exit" => match value.parse() {
+                Ok(policy) => {
+                    map.push(OnExit(policy));
+                    vec![]
+                }
+                Err(_) => vec![(value, String::from("expected `detach` or `wait [TIMEOUT_MS]` after `on-exit`"))]
+            },
+            "x-" => {
+                vec![(directive, String::from("expected a name after `x-`"))]
+            }
+            _ if directive.starts_with("x-") => {
+                let (v, warnings) = expander.expand(value);
+                map.push(Custom(String::from(&directive[2..]), v));
+                warnings
+            }
+            _ => vec![(directive, format!("unknown directive `{}`", directive))]
+        }
+    }
+
+    /// Inside `when`, `entry` replaces the entry point of the preamble rather than adding another one.
+    fn parse_entry_override<'l>(expander: &Expander, line: &'l str, map: &mut Vec<AdditionalParameter>) -> Vec<Problem<'l>> {
+        let (directive, value) = line.split_once(" ").unwrap_or((line, ""));
+        if map.iter().any(|p| matches!(p, EntryOverride(_))) {
+            return vec![(directive, String::from("`entry` specified twice, second ignored"))];
+        }
+
+        match parse_entry(expander, value) {
+            Ok((entry, _)) if entry.order != 0 || entry.continue_on_failure => {
+                vec![(value, String::from("`order` and `continue` are not allowed for `entry` inside `when`"))]
+            }
+            Ok((entry, warnings)) => {
+                map.push(EntryOverride(entry));
+                warnings
+            }
+            Err(warning) => vec![warning]
+        }
+    }
+}
+
+/// Parses an entry point of the form
+/// `AssemblyPath::QualifiedClassName!EntryFunction [order=N] [continue] [unmanaged | delegate=TYPE]`,
+/// where the assembly path is relative to the configuration file, and excludes the `.dll` extension.
+/// `delegate=` takes the rest of the line, as assembly qualified type names contain spaces.
+fn parse_entry<'v>(expander: &Expander, value: &'v str) -> Result<(EntryPoint, Vec<Problem<'v>>), Problem<'v>> {
+    let mut path = value;
+    let mut order = 0;
+    let mut continue_on_failure = false;
+    let mut signature = EntrySignature::Default;
+    let mut warnings = Vec::new();
+    if let Some((rest, delegate)) = value.split_once(" delegate=") {
+        let option = &value[rest.len() + 1..];
+        match parse_signature(delegate) {
+            Some(EntrySignature::Delegate(delegate)) => signature = EntrySignature::Delegate(delegate),
+            _ => warnings.push((option, format!("invalid delegate type `{}`", delegate))),
+        }
+        path = rest;
+    }
+
+    // Other options are taken from the end, so that the assembly path may contain spaces.
+    while let Some((rest, option)) = path.rsplit_once(' ') {
+        if option == "continue" {
+            continue_on_failure = true;
+        } else if option == "unmanaged" {
+            signature = EntrySignature::UnmanagedCallersOnly;
+        } else if let Some(n) = option.strip_prefix("order=") {
+            match n.parse() {
+                Ok(n) => order = n,
+                Err(_) => warnings.push((option, format!("invalid entry order `{}`", n))),
+            }
+        } else {
+            break;
+        }
+        path = rest;
+    }
+
+    let (mut entry, path_warnings) = parse_entry_path(expander, path).map_err(|(_, reason)| (value, reason))?;
+    entry.order = order;
+    entry.continue_on_failure = continue_on_failure;
+    entry.signature = signature;
+    warnings.extend(path_warnings);
+    Ok((entry, warnings))
+}
+
+/// Parses `AssemblyPath::QualifiedClassName!EntryFunction` as is, without any options, into an entry point with the
+/// default order and signature.
+fn parse_entry_path<'v>(expander: &Expander, path: &'v str) -> Result<(EntryPoint, Vec<Problem<'v>>), Problem<'v>> {
+    let invalid = || (path, String::from("expected an entry point of the form `AssemblyPath::QualifiedClassName!EntryFunction`"));
+
+    let (asm_path, entry_cls, entry_fn) = split_short_entry(path).ok_or_else(invalid)?;
+    let (asm_path, warnings) = expander.expand(asm_path);
+    let asm = Path::new(&asm_path).file_name()
+        .and_then(|asm| asm.to_str())
+        .map(String::from)
+        .ok_or_else(invalid)?;
+
+    let mut assembly_fname_path = PathBuf::from(expander.origin);
+    assembly_fname_path.push(asm_path);
+    let mut assembly_fname_path = assembly_fname_path.into_os_string();
+    assembly_fname_path.push(".dll");
+
+    let entry = (|| Some(EntryPoint {
+        assembly: PdCString::from_os_str(&assembly_fname_path).ok()?,
+        type_name: PdCString::from_str(&format!("{}, {}", entry_cls, asm)).ok()?,
+        method: PdCString::from_str(entry_fn).ok()?,
+        order: 0,
+        continue_on_failure: false,
+        signature: EntrySignature::Default,
+    }))().ok_or_else(invalid)?;
+    Ok((entry, warnings))
+}
+
+/// Parses the value of `delegate`, which is either `unmanaged` or an assembly qualified delegate type.
+fn parse_signature(value: &str) -> Option<EntrySignature> {
+    match value {
+        "" => None,
+        "unmanaged" => Some(EntrySignature::UnmanagedCallersOnly),
+        delegate => PdCString::from_str(delegate).ok().map(EntrySignature::Delegate),
+    }
+}
+
+/// Splits a short-format entry point `AssemblyName::QualifiedClassName!EntryFunction` into its parts.
+fn split_short_entry(line: &str) -> Option<(&str, &str, &str)> {
+    let (asm, rest) = line.split_once("::")?;
+    let (entry_cls, entry_fn) = rest.split_once("!")?;
+    Some((asm, entry_cls, entry_fn))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{OsStr, OsString};
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+    use netcorehost::pdcstr;
+    use netcorehost::pdcstring::PdCString;
+    use proptest::prelude::*;
+    use crate::config::{config_candidates, registry_only, AdditionalParameter, BootMode, Condition, ConditionalSection, ConfigError, ConfigFormat,
+                        EntryPoint, EntrySignature, EnvironmentAction, ExitPolicy, ExpansionContext, FailurePolicy, HostContext, LogSink,
+                        Registry, VulkanEntryPoint, VulkanInitParams, MAX_PARENT_DEPTH};
+    use crate::config::condition::glob_match;
+    use crate::services::LogLevel;
+    use crate::LoadConfig;
+
+    /// Held by every test that changes the environment of the test process, as tests run in parallel.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn lock_env() -> MutexGuard<'static, ()> {
+        ENV_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[test]
+    fn test_parse_short() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main";
+        let config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.runtime_config.as_ref(), pdcstr!("Assembly.runtimeconfig.json"));
+        assert_eq!(config.type_name.as_ref(), pdcstr!("TestInject.EntryPoint, Assembly"));
+        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("Assembly.dll"));
+        assert_eq!(config.entry_method.as_ref(), pdcstr!("Main"));
+        assert_eq!(config.additional_params.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_short_params() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+hostfxr HOSTFX
+env TESTENV=TEST
+env TESTENV2=TEST2
+dotnetroot DOTNETROOT
+";
+        let config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.runtime_config.as_ref(), pdcstr!("Assembly.runtimeconfig.json"));
+        assert_eq!(config.type_name.as_ref(), pdcstr!("TestInject.EntryPoint, Assembly"));
+        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("Assembly.dll"));
+        assert_eq!(config.entry_method.as_ref(), pdcstr!("Main"));
+        assert_eq!(config.environment_variables().collect::<Vec<_>>(), vec![
+            (OsString::from_str("TESTENV").unwrap().as_os_str(), OsString::from_str("TEST").unwrap().as_os_str()),
+            (OsString::from_str("TESTENV2").unwrap().as_os_str(), OsString::from_str("TEST2").unwrap().as_os_str())
+        ]);
+        assert_eq!(config.hostfxr_path(), Some(Path::new("HOSTFX")));
+        assert_eq!(config.dotnetroot_path(), Some(Path::new("DOTNETROOT")));
+    }
+
+    #[test]
+    fn test_parse_long() {
+        let kaku_co = "kaku_l
+Assembly.runtimeconfig.json
 Assembly.dll
 TestInject.EntryPoint, Assembly
 Main";
@@ -309,4 +1342,1299 @@ dotnetroot DOTNETROOT
         assert_eq!(config.hostfxr_path(), Some(Path::new("/HOSTFX")));
         assert_eq!(config.dotnetroot_path(), Some(Path::new("/DOTNETROOT")));
     }
+
+    #[test]
+    fn test_parse_warnings() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+hostfx HOSTFX
+hostfxr HOSTFX
+hostfxr HOSTFX2
+env TESTENV
+
+vulkan two CreateDevice
+vulkan 2 CreateSwapchain
+";
+        let config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.hostfxr_path(), Some(Path::new("HOSTFX")));
+        assert_eq!(config.vulkan(), None);
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.column, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (3, 1, "unknown directive `hostfx`"),
+            (5, 1, "`hostfxr` specified twice, second ignored"),
+            (6, 5, "expected `KEY=VALUE` after `env`"),
+            (8, 8, "invalid Vulkan loader version `two`"),
+            (9, 10, "unknown Vulkan entry point `CreateSwapchain`"),
+        ]);
+        assert_eq!(config.warnings[0].text, "hostfx HOSTFX");
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+strict
+hostfx HOSTFX
+";
+        let err = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::StrictModeViolation(warnings)) => {
+                assert_eq!(warnings.len(), 1);
+                assert_eq!(warnings[0].line, 4);
+                assert_eq!(warnings[0].reason, "unknown directive `hostfx`");
+            }
+            e => panic!("expected strict mode violation, got {:?}", e)
+        }
+
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+strict
+hostfxr HOSTFX
+";
+        let config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
+        assert!(config.is_strict());
+    }
+
+    #[test]
+    fn test_parse_invalid_preamble() {
+        let kaku_co = "kaku_l
+Assembly.runtimeconfig.json
+Assembly.dll";
+        let err = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::InvalidConfig(diagnostic)) => {
+                assert_eq!(diagnostic.line, 4);
+                assert_eq!(diagnostic.reason, "expected the qualified name of the entry-point class");
+            }
+            e => panic!("expected invalid config, got {:?}", e)
+        }
+
+        let kaku_co = "kaku_s
+Assembly:TestInject.EntryPoint!Main";
+        let err = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::InvalidConfig(diagnostic)) => {
+                assert_eq!((diagnostic.line, diagnostic.column), (2, 1));
+                assert_eq!(diagnostic.text, "Assembly:TestInject.EntryPoint!Main");
+            }
+            e => panic!("expected invalid config, got {:?}", e)
+        }
+    }
+
+    #[test]
+    fn test_parse_expansion() {
+        let _env = lock_env();
+        std::env::set_var("SABINOKAKU_TEST_EXPANSION", "/opt/dotnet");
+        let kaku_co = "kaku_l
+${ORIGIN}/Assembly.runtimeconfig.json
+${MODULE_DIR}/Assembly.dll
+TestInject.EntryPoint, Assembly
+Main
+dotnetroot ${SABINOKAKU_TEST_EXPANSION}
+hostfxr ${EXE_DIR}/host/hostfxr.so
+env PRICE=$$5 and $~ and $5
+env HOME_DIR=~
+";
+        let context = ExpansionContext {
+            module_dir: Some(PathBuf::from("/usr/lib/sabinokaku")),
+            exe_dir: Some(PathBuf::from("/usr/bin")),
+        };
+        let config = LoadConfig::try_parse_with(PathBuf::from("/app/kaku.co"), &kaku_co, &context).unwrap();
+        assert_eq!(config.runtime_config.as_ref(), pdcstr!("/app/Assembly.runtimeconfig.json"));
+        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("/usr/lib/sabinokaku/Assembly.dll"));
+        assert_eq!(config.dotnetroot_path(), Some(Path::new("/opt/dotnet")));
+        assert_eq!(config.hostfxr_path(), Some(Path::new("/usr/bin/host/hostfxr.so")));
+
+        let env = config.environment_variables().collect::<Vec<_>>();
+        assert_eq!(env[0].1, OsStr::new("$5 and ~ and $5"));
+        if let Some(home) = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" }) {
+            assert_eq!(env[1].1, home);
+        }
+        assert!(config.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_expansion_warnings() {
+        let kaku_co = "kaku_l
+Assembly.runtimeconfig.json
+${MODULE_DIR}/Assembly.dll
+TestInject.EntryPoint, Assembly
+Main
+env TEST=${SABINOKAKU_TEST_UNDEFINED}
+hostfxr ${ORIGIN/hostfxr.so
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("/Assembly.dll"));
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.column, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (3, 1, "undefined variable `MODULE_DIR`"),
+            (6, 10, "undefined variable `SABINOKAKU_TEST_UNDEFINED`"),
+            (7, 9, "unterminated variable, expected `}`"),
+        ]);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+strict
+hostfxr runtime/host/hostfxr.so
+env TESTENV=TEST
+dotnetroot /opt/dotnet
+vulkan 2 CreateDevice
+";
+        let root = Path::new("/app/kaku.co");
+        let config = LoadConfig::try_parse(root.to_path_buf(), &kaku_co).unwrap();
+        assert_eq!(config.write(root, ConfigFormat::Short).unwrap(), kaku_co);
+        assert_eq!(config.write(root, ConfigFormat::Long).unwrap(), "kaku_l
+Assembly.runtimeconfig.json
+Assembly.dll
+TestInject.EntryPoint, Assembly
+Main
+strict
+hostfxr runtime/host/hostfxr.so
+env TESTENV=TEST
+dotnetroot /opt/dotnet
+vulkan 2 CreateDevice
+");
+    }
+
+    #[test]
+    fn test_write_short_unrepresentable() {
+        let kaku_co = "kaku_l
+runtime/Assembly.runtimeconfig.json
+bin/Assembly.dll
+TestInject.EntryPoint, Assembly
+Main";
+        let root = Path::new("/app/kaku.co");
+        let config = LoadConfig::try_parse(root.to_path_buf(), &kaku_co).unwrap();
+        assert!(matches!(config.write(root, ConfigFormat::Short), Err(ConfigError::Unrepresentable(_))));
+        assert_eq!(config.write(root, ConfigFormat::Long).unwrap(), format!("{}\n", kaku_co));
+    }
+
+    fn arb_path() -> impl Strategy<Value = String> {
+        "[a-z][a-z0-9_.]{0,8}(/[a-z][a-z0-9_.]{0,8}){0,2}"
+    }
+
+    /// Mostly printable text on a single line, and sometimes text spanning several, which can not be written.
+    fn arb_value() -> impl Strategy<Value = String> {
+        prop_oneof![9 => "[ -~]{0,20}", 1 => "[ -~\n]{0,20}"]
+    }
+
+    fn arb_params() -> impl Strategy<Value = Vec<AdditionalParameter>> {
+        // Names that are empty, or contain `=` or a space, can not be written.
+        let env_key = prop_oneof![9 => "[A-Z_][A-Z0-9_]{0,10}", 1 => "[A-Z_ =]{0,6}"];
+        let env = (env_key, arb_value(), 0..5)
+            .prop_map(|(k, v, mode)| {
+                let (k, v) = (OsString::from(k), OsString::from(v));
+                match mode {
+                    0 => AdditionalParameter::EnvironmentVariable(k, v),
+                    1 => AdditionalParameter::EnvironmentUnset(k),
+                    2 => AdditionalParameter::EnvironmentDefault(k, v),
+                    3 => AdditionalParameter::EnvironmentPrepend(k, v),
+                    _ => AdditionalParameter::EnvironmentAppend(k, v),
+                }
+            }).boxed();
+        let vulkan = (2..5u32, prop_oneof![Just(VulkanEntryPoint::CreateDevice), Just(VulkanEntryPoint::CreateInstance)])
+            .prop_map(|(loader_version, entry)| AdditionalParameter::Vulkan(VulkanInitParams { loader_version, entry }));
+        let policy = prop_oneof![
+            Just(FailurePolicy::Ignore),
+            Just(FailurePolicy::Log),
+            prop::option::of(any::<i32>()).prop_map(FailurePolicy::Exit),
+            Just(FailurePolicy::Abort),
+        ];
+        let signature = prop_oneof![
+            Just(EntrySignature::Default),
+            Just(EntrySignature::UnmanagedCallersOnly),
+            "[A-Za-z][A-Za-z0-9_.]{0,12}, [A-Za-z]{1,8}".prop_map(|d| EntrySignature::Delegate(PdCString::from_str(&d).unwrap())),
+        ];
+        let entry_point = (arb_path(), "[A-Za-z][A-Za-z0-9_.]{0,12}", "[A-Za-z_][A-Za-z0-9_]{0,12}", -3..3i32, any::<bool>(), signature)
+            .prop_map(|(asm_path, entry_cls, entry_fn, order, continue_on_failure, signature)| {
+                let asm = Path::new(&asm_path).file_name().unwrap().to_str().unwrap().to_string();
+                EntryPoint {
+                    assembly: PdCString::from_str(&format!("/app/{}.dll", asm_path)).unwrap(),
+                    type_name: PdCString::from_str(&format!("{}, {}", entry_cls, asm)).unwrap(),
+                    method: PdCString::from_str(&entry_fn).unwrap(),
+                    order,
+                    continue_on_failure,
+                    signature
+                }
+            }).boxed();
+        let entry = entry_point.clone().prop_map(AdditionalParameter::Entry);
+        // Conditions that are empty or contain a space can not be written.
+        let condition = prop_oneof![
+            4 => "[a-z*?][a-z0-9*?.]{0,8}".prop_map(Condition::Exe),
+            4 => "-{0,2}[a-z*?][a-z0-9*?-]{0,8}".prop_map(Condition::Arg),
+            4 => "[A-Z_][A-Z0-9_]{0,10}".prop_map(|k| Condition::HasEnv(OsString::from(k))),
+            4 => any::<u32>().prop_map(Condition::Uid),
+            1 => "[a-z ]{0,6}".prop_map(Condition::Exe),
+            1 => "[A-Z ]{0,6}".prop_map(|k| Condition::HasEnv(OsString::from(k))),
+        ];
+        // A section without conditions can not be written.
+        let conditions = prop_oneof![9 => prop::collection::vec(condition, 1..3), 1 => Just(Vec::new())];
+        let section = (
+            conditions,
+            prop::collection::vec(env.clone(), 0..3),
+            prop::option::of(entry_point.prop_map(|entry| {
+                AdditionalParameter::EntryOverride(EntryPoint { order: 0, continue_on_failure: false, ..entry })
+            })),
+            prop::option::of(Just(AdditionalParameter::Skip)),
+        ).prop_map(|(conditions, mut params, entry, skip)| {
+            params.extend(entry.into_iter().chain(skip));
+            AdditionalParameter::Conditional(ConditionalSection { conditions, params })
+        });
+        // `unmanaged` is taken as `delegate unmanaged`, so it can not be written as a delegate type.
+        let delegate = prop_oneof![
+            Just(EntrySignature::UnmanagedCallersOnly),
+            "[A-Za-z][A-Za-z0-9_.]{0,12}, [A-Za-z]{1,8}".prop_map(|d| EntrySignature::Delegate(PdCString::from_str(&d).unwrap())),
+            Just(EntrySignature::Delegate(PdCString::from_str("unmanaged").unwrap())),
+        ];
+        (
+            prop::collection::vec(env, 0..4),
+            prop::option::of(arb_path().prop_map(|p| AdditionalParameter::Hostfxr(Path::new("/app").join(p)))),
+            prop::option::of(arb_path().prop_map(|p| AdditionalParameter::DotNetRoot(Path::new("/opt").join(p)))),
+            prop::option::of(vulkan),
+            prop::option::of(Just(AdditionalParameter::Strict)),
+            prop::collection::vec(entry, 0..3),
+            prop::collection::vec(arb_value().prop_map(|arg| AdditionalParameter::Argument(OsString::from(arg))), 0..3),
+            prop::collection::vec(("[A-Za-z_][A-Za-z0-9_.]{0,16}", arb_value())
+                .prop_map(|(k, v)| AdditionalParameter::RuntimeProperty(OsString::from(k), OsString::from(v))), 0..3),
+            prop::option::of(policy.clone().prop_map(AdditionalParameter::OnFailure)),
+            prop::option::of(policy.prop_map(AdditionalParameter::OnReturn)),
+            // Names that are empty or contain a space can not be written.
+            prop::collection::vec((prop_oneof![9 => "[a-z][a-z0-9.-]{0,12}", 1 => "[a-z ]{0,6}"], arb_value())
+                .prop_map(|(k, v)| AdditionalParameter::Custom(k, OsString::from(v))), 0..3),
+            (
+                prop::option::of(prop::option::of(prop_oneof![Just(LogLevel::Error), Just(LogLevel::Info), Just(LogLevel::Trace)])
+                    .prop_map(AdditionalParameter::Log)),
+                prop::option::of(arb_path().prop_map(|p| AdditionalParameter::LogTo(LogSink::File(Path::new("/app").join(p))))),
+                prop::option::of(arb_path().prop_map(|p| AdditionalParameter::Report(Path::new("/app").join(p)))),
+                prop::option::of(prop_oneof![
+                    Just(BootMode::Async),
+                    Just(BootMode::Blocking),
+                    prop::option::of(any::<u32>()).prop_map(BootMode::Ready),
+                    Just(BootMode::MainThread),
+                ].prop_map(AdditionalParameter::Boot)),
+                prop::option::of(prop_oneof![
+                    Just(ExitPolicy::Detach),
+                    prop::option::of(any::<u32>()).prop_map(ExitPolicy::Wait),
+                ].prop_map(AdditionalParameter::OnExit)),
+                prop::option::of(delegate.prop_map(AdditionalParameter::Signature)),
+                prop::option::of(prop::option::of("[A-Z_][A-Z0-9_]{0,10}".prop_map(OsString::from))
+                    .prop_map(AdditionalParameter::RestoreEnvironment)),
+                prop::option::of(Just(AdditionalParameter::ForwardArguments)),
+                prop::collection::vec(section, 0..3),
+            ),
+        ).prop_map(|(mut params, hostfxr, dotnetroot, vulkan, strict, entries, args, properties, on_failure, on_return, custom,
+                     (log, log_to, report, boot, on_exit, signature, restore_env, forward_args, sections))| {
+            params.extend(hostfxr.into_iter().chain(dotnetroot).chain(vulkan).chain(strict).chain(entries).chain(args)
+                .chain(properties).chain(on_failure).chain(on_return).chain(custom).chain(log).chain(log_to).chain(report).chain(boot).chain(on_exit)
+                .chain(signature).chain(restore_env).chain(forward_args).chain(sections)
+                .chain([AdditionalParameter::LogTo(LogSink::Syslog)]));
+            params
+        }).prop_shuffle()
+    }
+
+    /// Whether the writer can not express `param`, of those [`arb_params`] generates.
+    fn is_unrepresentable(param: &AdditionalParameter) -> bool {
+        let bad_name = |k: &OsStr| k.is_empty() || k.to_string_lossy().contains(['=', ' ']);
+        let multi_line = |v: &OsStr| v.to_string_lossy().contains('\n');
+        match param {
+            AdditionalParameter::Conditional(section) => section.conditions.is_empty()
+                || section.conditions.iter().any(|condition| match condition {
+                    Condition::Exe(glob) | Condition::Arg(glob) => glob.is_empty() || glob.contains(' '),
+                    Condition::HasEnv(name) => bad_name(name),
+                    Condition::Uid(_) => false,
+                })
+                || section.params.iter().any(is_unrepresentable),
+            AdditionalParameter::EnvironmentVariable(k, v) | AdditionalParameter::EnvironmentDefault(k, v)
+            | AdditionalParameter::EnvironmentPrepend(k, v) | AdditionalParameter::EnvironmentAppend(k, v)
+            | AdditionalParameter::RuntimeProperty(k, v) => bad_name(k) || multi_line(v),
+            AdditionalParameter::EnvironmentUnset(k) => bad_name(k),
+            AdditionalParameter::Signature(EntrySignature::Delegate(delegate)) => delegate.to_os_string() == "unmanaged",
+            AdditionalParameter::Argument(arg) => arg.is_empty() || multi_line(arg),
+            AdditionalParameter::Custom(k, v) => k.is_empty() || k.contains(' ') || multi_line(v),
+            _ => false
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_write_parse_long(runtime_config in arb_path(), assembly in arb_path(),
+                                 entry_type in "[A-Za-z][A-Za-z0-9_.]{0,12}, [A-Za-z][A-Za-z0-9_.]{0,8}",
+                                 entry_fn in "[A-Za-z_][A-Za-z0-9_]{0,12}", params in arb_params()) {
+            let root = Path::new("/app/kaku.co");
+            let config = LoadConfig::new(
+                PdCString::from_os_str(Path::new("/app").join(runtime_config)).unwrap(),
+                PdCString::from_os_str(Path::new("/app").join(assembly)).unwrap(),
+                PdCString::from_str(&entry_type).unwrap(),
+                PdCString::from_str(&entry_fn).unwrap(),
+                params
+            );
+            let written = config.write(root, ConfigFormat::Long);
+            if config.additional_params.iter().any(is_unrepresentable) {
+                prop_assert!(matches!(written, Err(ConfigError::Unrepresentable(_))));
+            } else {
+                prop_assert_eq!(LoadConfig::try_parse(root.to_path_buf(), &written.unwrap()).unwrap(), config);
+            }
+        }
+
+        #[test]
+        fn test_write_parse_short(asm in "[A-Za-z][A-Za-z0-9_.]{0,8}", entry_cls in "[A-Za-z][A-Za-z0-9_.]{0,12}",
+                                  entry_fn in "[A-Za-z_][A-Za-z0-9_]{0,12}", params in arb_params()) {
+            let root = Path::new("/app/kaku.co");
+            let config = LoadConfig::new(
+                PdCString::from_str(&format!("/app/{}.runtimeconfig.json", asm)).unwrap(),
+                PdCString::from_str(&format!("/app/{}.dll", asm)).unwrap(),
+                PdCString::from_str(&format!("{}, {}", entry_cls, asm)).unwrap(),
+                PdCString::from_str(&entry_fn).unwrap(),
+                params
+            );
+            let written = config.write(root, ConfigFormat::Short);
+            if config.additional_params.iter().any(is_unrepresentable) {
+                prop_assert!(matches!(written, Err(ConfigError::Unrepresentable(_))));
+            } else {
+                prop_assert_eq!(LoadConfig::try_parse(root.to_path_buf(), &written.unwrap()).unwrap(), config);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_structured() {
+        let kaku_co = "#kaku_t
+[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+";
+        let config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.runtime_config.as_ref(), pdcstr!("Assembly.runtimeconfig.json"));
+        assert_eq!(config.type_name.as_ref(), pdcstr!("TestInject.EntryPoint, Assembly"));
+        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("Assembly.dll"));
+        assert_eq!(config.entry_method.as_ref(), pdcstr!("Main"));
+        assert_eq!(config.additional_params.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_structured_params() {
+        let kaku_toml = "[entry]
+assembly = \"Assembly.dll\"
+type = \"TestInject.EntryPoint, Assembly\"
+method = \"Main\"
+
+[runtime]
+hostfxr = \"HOSTFX\"
+dotnetroot = \"DOTNETROOT\"
+
+[env]
+TESTENV = \"TEST\"
+TESTENV2 = \"TEST2\"
+
+[vulkan]
+loader_version = 2
+entry = \"CreateDevice\"
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/kaku.toml"), &kaku_toml).unwrap();
+        assert_eq!(config.runtime_config.as_ref(), pdcstr!("/Assembly.runtimeconfig.json"));
+        assert_eq!(config.type_name.as_ref(), pdcstr!("TestInject.EntryPoint, Assembly"));
+        assert_eq!(config.entry_assembly.as_ref(), pdcstr!("/Assembly.dll"));
+        assert_eq!(config.entry_method.as_ref(), pdcstr!("Main"));
+        assert_eq!(config.environment_variables().collect::<Vec<_>>(), vec![
+            (OsString::from_str("TESTENV").unwrap().as_os_str(), OsString::from_str("TEST").unwrap().as_os_str()),
+            (OsString::from_str("TESTENV2").unwrap().as_os_str(), OsString::from_str("TEST2").unwrap().as_os_str())
+        ]);
+        assert_eq!(config.hostfxr_path(), Some(Path::new("/HOSTFX")));
+        assert_eq!(config.dotnetroot_path(), Some(Path::new("/DOTNETROOT")));
+        assert_eq!(config.vulkan(), Some(&VulkanInitParams { loader_version: 2, entry: VulkanEntryPoint::CreateDevice }));
+    }
+
+    #[test]
+    fn test_parse_formats_equivalent() {
+        // The short format, as the writer writes it, and an equivalent configuration in another format.
+        let kaku_s = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+hostfxr HOSTFX
+dotnetroot DOTNETROOT
+env TESTENV=TEST
+vulkan 2 CreateInstance
+";
+        let pairs = [(kaku_s, "kaku_l
+Assembly.runtimeconfig.json
+Assembly.dll
+TestInject.EntryPoint, Assembly
+Main
+hostfxr HOSTFX
+dotnetroot DOTNETROOT
+env TESTENV=TEST
+vulkan 2 CreateInstance
+"), (kaku_s, "# kaku_t
+[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+
+[runtime]
+hostfxr = \"HOSTFX\"
+dotnetroot = \"DOTNETROOT\"
+
+[env]
+TESTENV = \"TEST\"
+
+[vulkan]
+loader_version = 2
+entry = \"CreateInstance\"
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+when exe game*.exe arg --editor
+env TESTENV=EDITOR
+entry Editor/Editor::TestInject.Editor!Main
+end
+when exe launcher.exe
+skip
+end
+", "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+
+[[when]]
+exe = \"game*.exe\"
+arg = \"--editor\"
+entry = \"Editor/Editor::TestInject.Editor!Main\"
+
+[when.env]
+TESTENV = \"EDITOR\"
+
+[[when]]
+exe = \"launcher.exe\"
+skip = true
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+entry plugins/Second::Second.Plugin!Main order=2
+entry plugins/Early::Early.Plugin!Init order=-1 continue
+", "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+
+[[entries]]
+path = \"plugins/Second::Second.Plugin!Main\"
+order = 2
+
+[[entries]]
+path = \"plugins/Early::Early.Plugin!Init\"
+order = -1
+continue = true
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+env-prepend PATH=/app/native
+env-unset DOTNET_ROOT
+env-default DOTNET_gcServer=0
+env-append LD_LIBRARY_PATH=/app/lib
+env DOTNET_MULTILEVEL_LOOKUP=0
+", "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+
+[env]
+PATH = { prepend = \"/app/native\" }
+DOTNET_ROOT = { unset = true }
+DOTNET_gcServer = { default = \"0\" }
+LD_LIBRARY_PATH = { append = \"/app/lib\" }
+DOTNET_MULTILEVEL_LOOKUP = \"0\"
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+restore-env
+", "restore_env = true
+[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+arg --profile
+arg /app/profiles/default.json
+", "args = [\"--profile\", \"${ORIGIN}/profiles/default.json\"]
+[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+property APP_CONTEXT_BASE_DIRECTORY=/app
+property System.GC.Server=true
+", "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+[properties]
+APP_CONTEXT_BASE_DIRECTORY = \"${ORIGIN}\"
+\"System.GC.Server\" = true
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+x-plugins /app/plugins
+x-plugins /app/more-plugins
+x-verbose
+", "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+[custom]
+plugins = [\"${ORIGIN}/plugins\", \"${ORIGIN}/more-plugins\"]
+verbose = \"\"
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+log debug
+log-to stderr
+log-to syslog
+log-to file logs/kaku.log
+", "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+[log]
+level = \"debug\"
+stderr = true
+syslog = true
+file = \"${ORIGIN}/logs/kaku.log\"
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+on-failure abort
+on-return ignore
+boot main-thread
+on-exit wait
+report boot.json
+", "boot = \"main-thread\"
+on_exit = \"wait\"
+report = \"boot.json\"
+on_failure = \"abort\"
+on_return = \"ignore\"
+[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+"), ("kaku_s
+Assembly::TestInject.EntryPoint!Main
+delegate TestInject.EntryDelegate, TestInject
+entry Plugins/Fast::Fast.Init!Run unmanaged
+", "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+delegate = \"TestInject.EntryDelegate, TestInject\"
+[[entries]]
+path = \"Plugins/Fast::Fast.Init!Run\"
+unmanaged = true
+"), ("kaku_x
+bin/MyTool.dll
+arg --verbose
+forward-args
+property System.GC.Server=true
+", "app = \"bin/MyTool.dll\"
+args = [\"--verbose\"]
+forward_args = true
+[properties]
+\"System.GC.Server\" = true
+")];
+
+        let root = Path::new("/app/kaku.co");
+        for (kaku_co, other) in pairs {
+            let short = LoadConfig::try_parse(root.to_path_buf(), &kaku_co).unwrap();
+            assert_eq!(short.warnings, vec![], "{}", kaku_co);
+            assert_eq!(short.write(root, ConfigFormat::Short).unwrap(), kaku_co, "{}", kaku_co);
+            let path = if other.starts_with("kaku_") { root.to_path_buf() } else { root.with_extension("toml") };
+            assert_eq!(LoadConfig::try_parse(path, &other).unwrap(), short, "{}", other);
+        }
+    }
+
+    #[test]
+    fn test_parse_structured_entries() {
+        let kaku_toml = "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+
+[[entries]]
+path = \"plugins/Early Access::Early.Plugin!Init order=-1\"
+continue = true
+delegate = \"Early.InitDelegate, Early Access\"
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.toml"), &kaku_toml).unwrap();
+        // Options are only taken from their own keys, so `order=-1` is part of the method name.
+        assert_eq!(config.entry_points()[1], EntryPoint {
+            assembly: PdCString::from_str("/app/plugins/Early Access.dll").unwrap(),
+            type_name: PdCString::from_str("Early.Plugin, Early Access").unwrap(),
+            method: PdCString::from_str("Init order=-1").unwrap(),
+            order: 0,
+            continue_on_failure: true,
+            signature: EntrySignature::Delegate(PdCString::from_str("Early.InitDelegate, Early Access").unwrap()),
+        });
+    }
+
+    #[test]
+    fn test_parse_structured_invalid_entry() {
+        let kaku_toml = "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+method = \"Main\"
+";
+        let err = LoadConfig::try_parse(PathBuf::from("kaku.toml"), &kaku_toml).unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::InvalidConfig(diagnostic)) => assert_eq!(diagnostic.line, 1),
+            e => panic!("expected invalid config, got {:?}", e)
+        }
+
+        let kaku_toml = "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+[vulkan]
+loader_version = two
+";
+        let err = LoadConfig::try_parse(PathBuf::from("kaku.toml"), &kaku_toml).unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::InvalidConfig(diagnostic)) => {
+                assert_eq!(diagnostic.line, 4);
+                assert_eq!(diagnostic.text, "loader_version = two");
+            }
+            e => panic!("expected invalid config, got {:?}", e)
+        }
+    }
+
+    #[test]
+    fn test_parse_when() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+env TESTENV=TEST
+when exe game*.exe arg --editor
+env TESTENV=EDITOR
+entry Editor/Editor::TestInject.Editor!Main
+end
+when exe launcher.exe
+skip
+end
+";
+        let root = PathBuf::from("/app/kaku.co");
+        let config = LoadConfig::try_parse(root.clone(), &kaku_co).unwrap();
+        assert_eq!(config.warnings, vec![]);
+        assert!(config.has_sections());
+        assert_eq!(config.write(&root, ConfigFormat::Short).unwrap(), kaku_co);
+
+        let host = |exe: &str, args: &[&str]| HostContext {
+            exe_path: Some(PathBuf::from(exe)),
+            args: args.iter().map(OsString::from).collect(),
+            uid: None
+        };
+
+        let mut editor = config.clone();
+        let section = editor.select_section(&host("/games/game64.exe", &["--editor"])).unwrap();
+        assert_eq!(section.conditions, vec![Condition::Exe(String::from("game*.exe")), Condition::Arg(String::from("--editor"))]);
+        assert!(!editor.has_sections());
+        assert!(!editor.skip_boot());
+        assert_eq!(editor.entry_assembly.as_ref(), pdcstr!("/app/Editor/Editor.dll"));
+        assert_eq!(editor.type_name.as_ref(), pdcstr!("TestInject.Editor, Editor"));
+        assert_eq!(editor.environment_variables().map(|(_, v)| v).collect::<Vec<_>>(), vec![OsStr::new("TEST"), OsStr::new("EDITOR")]);
+
+        let mut game = config.clone();
+        assert_eq!(game.select_section(&host("/games/game64.exe", &[])), None);
+        assert!(!game.has_sections());
+        assert_eq!(game.entry_assembly.as_ref(), pdcstr!("/app/Assembly.dll"));
+        assert_eq!(game.environment_variables().count(), 1);
+
+        let mut launcher = config;
+        assert!(launcher.select_section(&host("/games/launcher.exe", &["--editor"])).is_some());
+        assert!(launcher.skip_boot());
+    }
+
+    #[test]
+    fn test_parse_when_warnings() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+skip
+when uid root
+hostfxr HOSTFX
+when exe a.exe
+end
+end
+when has-env TESTENV
+";
+        let mut config = LoadConfig::try_parse(PathBuf::from("kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.column, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (3, 1, "`skip` is only allowed inside `when`"),
+            (4, 10, "invalid uid `root`"),
+            (5, 1, "`hostfxr` is not allowed inside `when`"),
+            (6, 1, "`when` blocks can not be nested"),
+            (8, 1, "`end` without a matching `when`"),
+            (9, 1, "`when` block is missing its `end`, ignored"),
+        ]);
+
+        // A section with an invalid condition never matches.
+        let host = HostContext { exe_path: None, args: vec![], uid: Some(0) };
+        assert_eq!(config.select_section(&host), None);
+        assert!(!config.skip_boot());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("game.exe", "game.exe"));
+        assert!(glob_match("game*.exe", "game64.exe"));
+        assert!(glob_match("game*.exe", "game.exe"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("g?me*", "game.x86_64"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("game*.exe", "game64.dll"));
+        assert!(!glob_match("g?me", "gme"));
+        assert!(!glob_match("", "game"));
+    }
+
+    #[test]
+    fn test_parse_entries() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+entry plugins/Second::Second.Plugin!Main order=2
+entry plugins/Early::Early.Plugin!Init order=-1 continue
+entry plugins/First::First.Plugin!Main continue
+entry plugins/Broken::Broken.Plugin!Main order=last
+when exe game.exe
+entry Editor::TestInject.Editor!Main continue
+end
+";
+        let root = PathBuf::from("/app/kaku.co");
+        let config = LoadConfig::try_parse(root.clone(), &kaku_co).unwrap();
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.column, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (6, 42, "invalid entry order `last`"),
+            (8, 7, "`order` and `continue` are not allowed for `entry` inside `when`"),
+        ]);
+
+        let entry_points = config.entry_points();
+        assert_eq!(entry_points.iter().map(|e| (e.to_string(), e.order, e.continue_on_failure)).collect::<Vec<_>>(), vec![
+            (String::from("Early.Plugin, Early!Init"), -1, true),
+            (String::from("TestInject.EntryPoint, Assembly!Main"), 0, false),
+            (String::from("First.Plugin, First!Main"), 0, true),
+            (String::from("Broken.Plugin, Broken!Main"), 0, false),
+            (String::from("Second.Plugin, Second!Main"), 2, false),
+        ]);
+        assert_eq!(entry_points[0].assembly.as_ref(), pdcstr!("/app/plugins/Early.dll"));
+    }
+
+    /// A fresh directory to write configuration files to, for tests that read them from disk.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sabinokaku-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("kaku.d")).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn test_load_include() {
+        let dir = test_dir("include");
+        std::fs::write(dir.join("kaku.co"), "kaku_s
+Assembly::TestInject.EntryPoint!Main
+include base/base.co
+hostfxr HOSTFX
+env TESTENV=MAIN
+").unwrap();
+        std::fs::create_dir_all(dir.join("base")).unwrap();
+        std::fs::write(dir.join("base/base.co"), "hostfxr BASE_HOSTFX
+dotnetroot BASE_DOTNETROOT
+env TESTENV=BASE
+hostfx TYPO
+").unwrap();
+        std::fs::write(dir.join("kaku.d/20-runtime.co"), "dotnetroot /opt/dotnet
+env TESTENV=DROPIN20
+").unwrap();
+        std::fs::write(dir.join("kaku.d/10-env.co"), "env TESTENV=DROPIN10\n").unwrap();
+        std::fs::write(dir.join("kaku.d/ignored.txt"), "env TESTENV=IGNORED\n").unwrap();
+
+        let config = LoadConfig::load(&dir.join("kaku.co"), &ExpansionContext::default()).unwrap();
+        assert_eq!(config.hostfxr_path(), Some(dir.join("HOSTFX").as_path()));
+        assert_eq!(config.dotnetroot_path(), Some(Path::new("/opt/dotnet")));
+        assert_eq!(config.environment_variables().map(|(_, v)| v).collect::<Vec<_>>(), vec![
+            OsStr::new("BASE"), OsStr::new("MAIN"), OsStr::new("DROPIN10"), OsStr::new("DROPIN20")
+        ]);
+        assert_eq!(config.warnings.len(), 1);
+        assert_eq!(config.warnings[0].file.as_deref(), Some(dir.join("base/base.co").as_path()));
+        assert_eq!(config.warnings[0].line, 4);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_str_without_drop_ins() {
+        let dir = test_dir("load-str");
+        std::fs::write(dir.join("base.co"), "env TESTENV=BASE\n").unwrap();
+        std::fs::write(dir.join("kaku.d/10-env.co"), "env TESTENV=DROPIN10\n").unwrap();
+
+        let input = "kaku_s\nAssembly::TestInject.EntryPoint!Main\ninclude base.co\nenv TESTENV=MAIN";
+        let config = LoadConfig::load_str(&dir.join("kaku.co"), input, &ExpansionContext::default()).unwrap();
+        assert_eq!(config.environment_variables().map(|(_, v)| v).collect::<Vec<_>>(), vec![
+            OsStr::new("BASE"), OsStr::new("MAIN")
+        ]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_drop_in_overrides() {
+        let dir = test_dir("drop-in-overrides");
+        std::fs::write(dir.join("kaku.co"), "kaku_s
+Assembly::TestInject.EntryPoint!Main
+delegate unmanaged
+on-return ignore
+").unwrap();
+        std::fs::write(dir.join("kaku.d/10-delegate.co"), "delegate TestInject.MainDelegate, TestInject
+on-return abort
+delegate unmanaged
+").unwrap();
+
+        let config = LoadConfig::load(&dir.join("kaku.co"), &ExpansionContext::default()).unwrap();
+        assert_eq!(config.entry_signature(),
+                   EntrySignature::Delegate(PdCString::from_str("TestInject.MainDelegate, TestInject").unwrap()));
+        assert_eq!(config.on_return(), FailurePolicy::Abort);
+        assert_eq!(config.additional_params.iter().filter(|p| p.is_single_valued()).count(), 2);
+        // Within the drop-in, the first still wins.
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (3, "`delegate` specified twice, second ignored"),
+        ]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_include_errors() {
+        let dir = test_dir("include-errors");
+        std::fs::write(dir.join("kaku.co"), "kaku_s
+Assembly::TestInject.EntryPoint!Main
+include a.co
+").unwrap();
+        std::fs::write(dir.join("a.co"), "include b.co\n").unwrap();
+        std::fs::write(dir.join("b.co"), "include ${ORIGIN}/a.co\n").unwrap();
+
+        let err = LoadConfig::load(&dir.join("kaku.co"), &ExpansionContext::default()).unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::IncludeCycle(chain)) => assert_eq!(chain, &vec![
+                dir.join("kaku.co"), dir.join("a.co"), dir.join("b.co"), dir.join("a.co")
+            ]),
+            e => panic!("expected include cycle, got {:?}", e)
+        }
+
+        std::fs::write(dir.join("b.co"), "include missing.co\n").unwrap();
+        let err = LoadConfig::load(&dir.join("kaku.co"), &ExpansionContext::default()).unwrap_err();
+        match err.downcast_ref::<ConfigError>() {
+            Some(ConfigError::MissingInclude(path)) => assert_eq!(path, &dir.join("missing.co")),
+            e => panic!("expected missing include, got {:?}", e)
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_config_candidates() {
+        let candidates = config_candidates(Some(Path::new("/opt/kaku/libkaku.so")), Some(Path::new("/games/foo/bin/game")),
+                                           Some(Path::new("/home/user/.config")), Some(Path::new("/home/user")));
+        assert_eq!(candidates, [
+            "/opt/kaku/kaku.co", "/opt/kaku/kaku.toml",
+            "/games/foo/bin/kaku.co", "/games/foo/bin/kaku.toml",
+            "/home/user/.config/sabinokaku/game.co",
+            "/games/foo/kaku.co", "/games/foo/kaku.toml",
+            "/games/kaku.co", "/games/kaku.toml",
+        ].iter().map(PathBuf::from).collect::<Vec<_>>());
+
+        // The module usually sits next to the executable, which is only tried once, and the root is never tried.
+        let candidates = config_candidates(Some(Path::new("/game/kaku.so")), Some(Path::new("/game/game.x86_64")), None, None);
+        assert_eq!(candidates, ["/game/kaku.co", "/game/kaku.toml"]
+            .iter().map(PathBuf::from).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_config_candidates_bounded() {
+        let parents = |exe: &str, home: Option<&str>| config_candidates(None, Some(Path::new(exe)), None, home.map(Path::new))
+            .into_iter()
+            .skip(2)
+            .filter(|p| p.ends_with("kaku.co"))
+            .map(|p| p.parent().unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+
+        // The walk stops before the home directory.
+        assert_eq!(parents("/home/user/games/foo/bin/game", Some("/home/user")),
+                   [PathBuf::from("/home/user/games/foo"), PathBuf::from("/home/user/games")]);
+        // And after a few levels.
+        assert_eq!(parents("/srv/a/b/c/d/e/bin/game", None).len(), MAX_PARENT_DEPTH);
+        assert_eq!(parents("/srv/a/b/c/d/e/bin/game", None)[0], PathBuf::from("/srv/a/b/c/d/e"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_registry_only() {
+        let game = Path::new("/opt/game/bin/game");
+        assert!(registry_only(Some(Path::new("/usr/lib/libkaku.so")), Some(game)));
+        assert!(!registry_only(Some(Path::new("/opt/game/bin/libkaku.so")), Some(game)));
+        assert!(!registry_only(Some(Path::new("/opt/game/bin/plugins/libkaku.so")), Some(game)));
+        assert!(!registry_only(None, Some(game)));
+    }
+
+    #[test]
+    fn test_config_candidates_world_writable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("sabinokaku-search-{}", std::process::id()));
+        let (shared, game) = (root.join("shared"), root.join("shared/game"));
+        std::fs::create_dir_all(game.join("bin")).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+        std::fs::set_permissions(&root, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let candidates = config_candidates(None, Some(&game.join("bin/game")), None, None);
+        let _ = std::fs::remove_dir_all(&root);
+        assert!(candidates.contains(&game.join("kaku.co")));
+        assert!(!candidates.contains(&shared.join("kaku.co")));
+        assert!(!candidates.contains(&root.join("kaku.co")));
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = Registry::parse(Path::new("/etc/sabinokaku/registry"), "# Payloads per program
+/usr/bin/game* = /opt/payloads/game/kaku.co
+
+game-launcher = launcher/kaku.toml
+*editor* = editor.co
+not a mapping
+");
+        assert_eq!(registry.mappings.len(), 3);
+        assert_eq!(registry.lookup(Path::new("/usr/bin/game-launcher")), Some(Path::new("/opt/payloads/game/kaku.co")));
+        assert_eq!(registry.lookup(Path::new("/opt/game-launcher")), Some(Path::new("/etc/sabinokaku/launcher/kaku.toml")));
+        assert_eq!(registry.lookup(Path::new("/opt/leveleditor")), Some(Path::new("/etc/sabinokaku/editor.co")));
+        assert_eq!(registry.lookup(Path::new("/usr/bin/bash")), None);
+    }
+
+    #[test]
+    fn test_parse_environment_actions() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+env-prepend PATH=/app/native
+env-unset DOTNET_ROOT
+env-default DOTNET_gcServer=0
+env-append LD_LIBRARY_PATH=/app/lib
+env DOTNET_MULTILEVEL_LOOKUP=0
+env-unset DOTNET_ROOT=/opt
+";
+        let root = PathBuf::from("/app/kaku.co");
+        let config = LoadConfig::try_parse(root.clone(), &kaku_co).unwrap();
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.column, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (8, 11, "expected a variable name after `env-unset`"),
+        ]);
+        assert_eq!(config.environment_actions().collect::<Vec<_>>(), vec![
+            EnvironmentAction::Prepend(OsStr::new("PATH"), OsStr::new("/app/native")),
+            EnvironmentAction::Unset(OsStr::new("DOTNET_ROOT")),
+            EnvironmentAction::Default(OsStr::new("DOTNET_gcServer"), OsStr::new("0")),
+            EnvironmentAction::Append(OsStr::new("LD_LIBRARY_PATH"), OsStr::new("/app/lib")),
+            EnvironmentAction::Set(OsStr::new("DOTNET_MULTILEVEL_LOOKUP"), OsStr::new("0")),
+        ]);
+        assert_eq!(config.write(&root, ConfigFormat::Short).unwrap(), kaku_co.rsplit_once("env-unset").unwrap().0);
+    }
+
+    #[test]
+    fn test_environment_action_apply() {
+        #[cfg(windows)]
+        let separator = ";";
+        #[cfg(not(windows))]
+        let separator = ":";
+
+        let (key, value) = (OsStr::new("PATH"), OsStr::new("/app"));
+        let current = Some(OsStr::new("/usr/bin"));
+        assert_eq!(EnvironmentAction::Set(key, value).apply_to(current), Some(OsString::from("/app")));
+        assert_eq!(EnvironmentAction::Unset(key).apply_to(current), None);
+        assert_eq!(EnvironmentAction::Default(key, value).apply_to(current), Some(OsString::from("/usr/bin")));
+        assert_eq!(EnvironmentAction::Default(key, value).apply_to(None), Some(OsString::from("/app")));
+        assert_eq!(EnvironmentAction::Prepend(key, value).apply_to(current), Some(OsString::from(format!("/app{}/usr/bin", separator))));
+        assert_eq!(EnvironmentAction::Append(key, value).apply_to(current), Some(OsString::from(format!("/usr/bin{}/app", separator))));
+        assert_eq!(EnvironmentAction::Append(key, value).apply_to(Some(OsStr::new(""))), Some(OsString::from("/app")));
+        assert_eq!(EnvironmentAction::Prepend(key, value).apply_to(None), Some(OsString::from("/app")));
+    }
+
+    #[test]
+    fn test_restore_environment() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+env SABINOKAKU_TEST_RESTORE_SET=CONFIG
+env SABINOKAKU_TEST_RESTORE_NEW=CONFIG
+env-append SABINOKAKU_TEST_RESTORE_SET=APPENDED
+env SABINOKAKU_TEST_RESTORE_KEPT=CONFIG
+restore-env SABINOKAKU_TEST_RESTORE_SET
+restore-env SABINOKAKU_TEST_RESTORE_NEW
+";
+        let root = PathBuf::from("/app/kaku.co");
+        let config = LoadConfig::try_parse(root.clone(), &kaku_co).unwrap();
+        assert_eq!(config.write(&root, ConfigFormat::Short).unwrap(), kaku_co);
+        assert!(!config.restores_environment(OsStr::new("SABINOKAKU_TEST_RESTORE_KEPT")));
+
+        let _env = lock_env();
+        std::env::set_var("SABINOKAKU_TEST_RESTORE_SET", "HOST");
+        let environment = config.apply_environment();
+        assert!(std::env::var_os("SABINOKAKU_TEST_RESTORE_SET").unwrap().to_str().unwrap().starts_with("CONFIG"));
+        assert_eq!(std::env::var_os("SABINOKAKU_TEST_RESTORE_NEW"), Some(OsString::from("CONFIG")));
+        environment.restore();
+        assert_eq!(std::env::var_os("SABINOKAKU_TEST_RESTORE_SET"), Some(OsString::from("HOST")));
+        assert_eq!(std::env::var_os("SABINOKAKU_TEST_RESTORE_NEW"), None);
+        assert_eq!(std::env::var_os("SABINOKAKU_TEST_RESTORE_KEPT"), Some(OsString::from("CONFIG")));
+
+        // Dropping the snapshot without calling `restore` restores too.
+        drop(config.apply_environment());
+        assert_eq!(std::env::var_os("SABINOKAKU_TEST_RESTORE_SET"), Some(OsString::from("HOST")));
+        assert_eq!(std::env::var_os("SABINOKAKU_TEST_RESTORE_NEW"), None);
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+arg --profile
+arg ${ORIGIN}/profiles/default.json
+arg
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.arguments().collect::<Vec<_>>(), vec![OsStr::new("--profile"), OsStr::new("/app/profiles/default.json")]);
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (5, "expected a value after `arg`"),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_runtime_properties() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+property APP_CONTEXT_BASE_DIRECTORY=${ORIGIN}
+property System.GC.Server=true
+property =orphan
+property Switch.Without.Value
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.runtime_properties().collect::<Vec<_>>(), vec![
+            (OsStr::new("APP_CONTEXT_BASE_DIRECTORY"), OsStr::new("/app")),
+            (OsStr::new("System.GC.Server"), OsStr::new("true")),
+        ]);
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (5, "expected `NAME=VALUE` after `property`"),
+            (6, "expected `NAME=VALUE` after `property`"),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_custom_directives() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+x-plugins ${ORIGIN}/plugins
+x-plugins ${ORIGIN}/more-plugins
+x-verbose
+x-
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.custom_directives("plugins").collect::<Vec<_>>(),
+                   vec![OsStr::new("/app/plugins"), OsStr::new("/app/more-plugins")]);
+        assert_eq!(config.custom_directives("verbose").collect::<Vec<_>>(), vec![OsStr::new("")]);
+        assert_eq!(config.custom_directives("missing").count(), 0);
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (6, "expected a name after `x-`"),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_logging() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+log debug
+log-to stderr
+log-to file ${ORIGIN}/logs/kaku.log
+log-to syslog
+log-to stderr
+log trace
+log-to journal
+log-to file
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.log_level(), Some(LogLevel::Debug));
+        assert_eq!(config.log_sinks(), vec![LogSink::Stderr, LogSink::File(PathBuf::from("/app/logs/kaku.log")), LogSink::Syslog]);
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (8, "`log` specified twice, second ignored"),
+            (9, "expected `stderr`, `syslog` or `file PATH` after `log-to`"),
+            (10, "expected `stderr`, `syslog` or `file PATH` after `log-to`"),
+        ]);
+
+        let quiet = "kaku_s\nAssembly::TestInject.EntryPoint!Main\nlog off";
+        assert_eq!(LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &quiet).unwrap().log_level(), None);
+        let default = "kaku_s\nAssembly::TestInject.EntryPoint!Main";
+        let default = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &default).unwrap();
+        assert_eq!(default.log_level(), None);
+        assert_eq!(default.log_sinks(), vec![LogSink::Stderr]);
+    }
+
+    #[test]
+    fn test_parse_boot_mode() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+boot ready 2500
+boot blocking
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.boot_mode(), BootMode::Ready(Some(2500)));
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (4, "`boot` specified twice, second ignored"),
+        ]);
+
+        for (mode, expected) in [("async", BootMode::Async), ("blocking", BootMode::Blocking),
+                                 ("ready", BootMode::Ready(None)), ("main-thread", BootMode::MainThread)] {
+            assert_eq!(mode.parse::<BootMode>(), Ok(expected));
+            assert_eq!(expected.to_string(), mode);
+        }
+        assert!("ready soon".parse::<BootMode>().is_err());
+        assert!("blocking 100".parse::<BootMode>().is_err());
+
+        let invalid = "kaku_s\nAssembly::TestInject.EntryPoint!Main\nboot eventually";
+        let invalid = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &invalid).unwrap();
+        assert_eq!(invalid.boot_mode(), BootMode::Async);
+        assert_eq!(invalid.warnings[0].reason, "expected `async`, `blocking`, `ready [TIMEOUT_MS]` or `main-thread` after `boot`");
+    }
+
+    #[test]
+    fn test_probe_boot_order() {
+        let dir = test_dir("probe");
+        std::fs::write(dir.join("kaku.co"), "kaku_l
+boot/Assembly.runtimeconfig.json
+boot/Assembly.dll
+TestInject.EntryPoint, Assembly
+Main
+when exe game
+boot blocking
+end
+boot eventually
+on-exit wait 3000
+boot ready 2500
+").unwrap();
+        std::fs::write(dir.join("kaku.d/10-boot.co"), "boot main-thread\n").unwrap();
+        std::fs::write(dir.join("kaku.toml"), "boot = \"main-thread\"\n[entry]\nshort = \"Assembly::TestInject.EntryPoint!Main\"\n").unwrap();
+
+        // Drop-ins are not read.
+        assert_eq!(LoadConfig::probe_boot_order(&dir.join("kaku.co")), (BootMode::Ready(Some(2500)), ExitPolicy::Wait(Some(3000))));
+        assert_eq!(LoadConfig::probe_boot_order(&dir.join("kaku.toml")), (BootMode::MainThread, ExitPolicy::Detach));
+        assert_eq!(LoadConfig::probe_boot_order(&dir.join("missing.co")), (BootMode::Async, ExitPolicy::Detach));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_on_exit() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+on-exit wait 3000
+on-exit detach
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.on_exit(), ExitPolicy::Wait(Some(3000)));
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (4, "`on-exit` specified twice, second ignored"),
+        ]);
+
+        assert_eq!("wait".parse::<ExitPolicy>(), Ok(ExitPolicy::Wait(None)));
+        assert_eq!(ExitPolicy::Wait(None).to_string(), "wait");
+        assert!("wait forever".parse::<ExitPolicy>().is_err());
+        assert!("detach 100".parse::<ExitPolicy>().is_err());
+
+        let default = "kaku_s\nAssembly::TestInject.EntryPoint!Main\non-exit linger";
+        let default = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &default).unwrap();
+        assert_eq!(default.on_exit(), ExitPolicy::Detach);
+        assert_eq!(default.warnings[0].reason, "expected `detach` or `wait [TIMEOUT_MS]` after `on-exit`");
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+report ${ORIGIN}/logs/boot.json
+report other.json
+report
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.report_path(), Some(Path::new("/app/logs/boot.json")));
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (4, "`report` specified twice, second ignored"),
+            (5, "expected a path after `report`"),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_application() {
+        let kaku_co = "kaku_x
+bin/MyTool.dll
+arg --verbose
+forward-args
+property System.GC.Server=true
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert!(config.warnings.is_empty());
+        assert!(config.is_application());
+        assert!(config.forwards_arguments());
+        assert_eq!(config.entry_assembly, PdCString::from_str("/app/bin/MyTool.dll").unwrap());
+        assert_eq!(config.runtime_config, PdCString::from_str("/app/bin/MyTool.runtimeconfig.json").unwrap());
+        assert_eq!(config.entry_points()[0].to_string(), "MyTool!Main");
+
+        let written = config.write(Path::new("/app/kaku.co"), ConfigFormat::Short).unwrap();
+        assert_eq!(written, kaku_co);
+
+        let error = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &"kaku_x\n").unwrap_err();
+        assert!(matches!(error.downcast_ref::<ConfigError>(), Some(ConfigError::InvalidConfig(d))
+            if d.line == 2 && d.reason == "expected the path to the application assembly"));
+    }
+
+    #[test]
+    fn test_parse_signatures() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+delegate unmanaged
+entry Plugins/Extra::Extra.Init!Run order=1 delegate=Extra.InitDelegate, Extra
+entry Plugins/Fast::Fast.Init!Run order=2 unmanaged
+delegate Other.Delegate, Other
+when exe game
+entry Game::Game.Init!Run
+end
+";
+        let mut config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (6, "`delegate` specified twice, second ignored"),
+        ]);
+        assert_eq!(config.entry_points().iter().map(|e| e.signature.clone()).collect::<Vec<_>>(), vec![
+            EntrySignature::UnmanagedCallersOnly,
+            EntrySignature::Delegate(PdCString::from_str("Extra.InitDelegate, Extra").unwrap()),
+            EntrySignature::UnmanagedCallersOnly,
+        ]);
+
+        // `delegate=` takes the rest of the line, and is written last.
+        let kaku_extra = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+entry Plugins/Extra::Extra.Init!Run order=1 continue delegate=Extra.InitDelegate, Extra
+";
+        let extra = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_extra).unwrap();
+        assert_eq!(extra.warnings, vec![]);
+        assert_eq!((extra.entry_points()[1].order, extra.entry_points()[1].continue_on_failure), (1, true));
+        assert_eq!(extra.write(Path::new("/app/kaku.co"), ConfigFormat::Short).unwrap(), kaku_extra);
+
+        // The entry point of a section replaces the signature along with it.
+        let host = HostContext { exe_path: Some(PathBuf::from("/usr/bin/game")), ..HostContext::default() };
+        config.select_section(&host).unwrap();
+        assert_eq!(config.entry_signature(), EntrySignature::Default);
+
+        let kaku_toml = "[entry]
+short = \"Assembly::TestInject.EntryPoint!Main\"
+delegate = \"TestInject.EntryDelegate, TestInject\"
+[[entries]]
+path = \"Plugins/Fast::Fast.Init!Run\"
+unmanaged = true
+";
+        let structured = LoadConfig::try_parse(PathBuf::from("/app/kaku.toml"), &kaku_toml).unwrap();
+        assert_eq!(structured.entry_points().iter().map(|e| e.signature.clone()).collect::<Vec<_>>(), vec![
+            EntrySignature::Delegate(PdCString::from_str("TestInject.EntryDelegate, TestInject").unwrap()),
+            EntrySignature::UnmanagedCallersOnly,
+        ]);
+        let written = structured.write(Path::new("/app/kaku.co"), ConfigFormat::Short).unwrap();
+        assert_eq!(written, "kaku_s
+Assembly::TestInject.EntryPoint!Main
+delegate TestInject.EntryDelegate, TestInject
+entry Plugins/Fast::Fast.Init!Run unmanaged
+");
+    }
+
+    #[test]
+    fn test_parse_policies() {
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+on-failure exit 3
+on-return exit
+on-return abort
+on-failure exit three
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.on_failure(), FailurePolicy::Exit(Some(3)));
+        assert_eq!(config.on_return(), FailurePolicy::Exit(None));
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (5, "`on-return` specified twice, second ignored"),
+            (6, "`on-failure` specified twice, second ignored"),
+        ]);
+
+        let kaku_co = "kaku_s
+Assembly::TestInject.EntryPoint!Main
+on-failure exit three
+";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &kaku_co).unwrap();
+        assert_eq!(config.on_failure(), FailurePolicy::Log);
+        assert_eq!(config.warnings.iter().map(|w| (w.line, w.column, w.reason.as_str())).collect::<Vec<_>>(), vec![
+            (3, 12, "expected `ignore`, `log`, `exit [CODE]` or `abort` after `on-failure`"),
+        ]);
+    }
 }
@@ -0,0 +1,213 @@
+use std::env::{args_os, current_exe};
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use crate::config::{AdditionalParameter, EntrySignature, LoadConfig, Problem};
+
+/// Information about the host process that conditional sections are matched against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostContext {
+    pub exe_path: Option<PathBuf>,
+    pub args: Vec<OsString>,
+    pub uid: Option<u32>,
+}
+
+impl HostContext {
+    /// The executable and arguments of the current process. The user id is platform specific, and left empty.
+    pub fn current() -> HostContext {
+        HostContext {
+            exe_path: current_exe().ok(),
+            args: args_os().skip(1).collect(),
+            uid: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// The host executable matches the glob. Globs without a path separator only match the file name.
+    Exe(String),
+    /// Any argument of the host process, excluding the executable, matches the glob.
+    Arg(String),
+    /// The environment variable is set in the host process.
+    HasEnv(OsString),
+    Uid(u32),
+}
+
+/// A `when` block, whose directives apply only if all of its conditions match the host process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionalSection {
+    pub conditions: Vec<Condition>,
+    pub params: Vec<AdditionalParameter>,
+}
+
+impl Condition {
+    pub fn matches(&self, host: &HostContext) -> bool {
+        match self {
+            Condition::Exe(glob) => matches!(&host.exe_path, Some(exe) if exe_match(glob, exe)),
+            Condition::Arg(glob) => host.args.iter()
+                .filter_map(|arg| arg.to_str())
+                .any(|arg| glob_match(glob, arg)),
+            Condition::HasEnv(name) => std::env::var_os(name).is_some(),
+            Condition::Uid(uid) => host.uid == Some(*uid),
+        }
+    }
+}
+
+impl ConditionalSection {
+    /// A section without any conditions never matches.
+    pub fn matches(&self, host: &HostContext) -> bool {
+        !self.conditions.is_empty() && self.conditions.iter().all(|c| c.matches(host))
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Exe(glob) => write!(f, "exe {}", glob),
+            Condition::Arg(glob) => write!(f, "arg {}", glob),
+            Condition::HasEnv(name) => write!(f, "has-env {}", name.to_string_lossy()),
+            Condition::Uid(uid) => write!(f, "uid {}", uid),
+        }
+    }
+}
+
+impl Display for ConditionalSection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "when")?;
+        for condition in &self.conditions {
+            write!(f, " {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the `<kind> <value>` pairs following `when`, returning warnings for any invalid pair.
+pub(super) fn parse_conditions(value: &str) -> (Vec<Condition>, Vec<Problem<'_>>) {
+    let mut conditions = Vec::new();
+    let mut warnings = Vec::new();
+
+    let mut tokens = value.split(' ').filter(|t| !t.is_empty());
+    while let Some(kind) = tokens.next() {
+        let value = match tokens.next() {
+            Some(value) => value,
+            None => {
+                warnings.push((kind, format!("expected a value after `{}`", kind)));
+                break;
+            }
+        };
+        match kind {
+            "exe" => conditions.push(Condition::Exe(String::from(value))),
+            "arg" => conditions.push(Condition::Arg(String::from(value))),
+            "has-env" => conditions.push(Condition::HasEnv(OsString::from(value))),
+            "uid" => match value.parse() {
+                Ok(uid) => conditions.push(Condition::Uid(uid)),
+                Err(_) => warnings.push((value, format!("invalid uid `{}`", value))),
+            },
+            _ => warnings.push((kind, format!("unknown condition `{}`", kind))),
+        }
+    }
+
+    if conditions.is_empty() && warnings.is_empty() {
+        warnings.push((value, String::from("expected a condition after `when`")));
+    }
+
+    // Rather than match more processes than intended, a section with an invalid condition never matches.
+    if !warnings.is_empty() {
+        conditions.clear();
+    }
+
+    (conditions, warnings)
+}
+
+/// Matches an executable against a glob. Globs without a path separator only match the file name.
+pub(crate) fn exe_match(glob: &str, exe: &Path) -> bool {
+    let subject = if glob.contains(['/', '\\']) {
+        Some(exe.as_os_str())
+    } else {
+        exe.file_name()
+    };
+    matches!(subject.and_then(|s| s.to_str()), Some(s) if glob_match(glob, s))
+}
+
+/// Matches `text` against a glob, where `*` matches any run of characters and `?` matches any one character.
+pub(crate) fn glob_match(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut g, mut t) = (0, 0);
+    // Position of the last `*` in the glob, and the text position it was tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                backtrack = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, star_t)) => {
+                    g = star + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star, star_t + 1));
+                }
+                None => return false
+            }
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+impl LoadConfig {
+    /// Applies the first `when` section that matches `host`, and removes all sections from the configuration.
+    ///
+    /// Environment variables in the section are applied after those outside it, and an `entry` in the
+    /// section replaces the entry point along with its signature. Returns the section that was applied,
+    /// if any.
+    pub fn select_section(&mut self, host: &HostContext) -> Option<ConditionalSection> {
+        let mut selected = None;
+        let mut params = Vec::with_capacity(self.additional_params.len());
+        for param in self.additional_params.drain(..) {
+            match param {
+                AdditionalParameter::Conditional(section) => {
+                    if selected.is_none() && section.matches(host) {
+                        selected = Some(section);
+                    }
+                }
+                param => params.push(param),
+            }
+        }
+        self.additional_params = params;
+
+        let section = selected?;
+        for param in &section.params {
+            match param {
+                AdditionalParameter::EntryOverride(entry) => {
+                    self.entry_assembly = entry.assembly.clone();
+                    self.type_name = entry.type_name.clone();
+                    self.entry_method = entry.method.clone();
+                    // The signature belongs to the entry point it replaces.
+                    self.additional_params.retain(|p| !matches!(p, AdditionalParameter::Signature(_)));
+                    if entry.signature != EntrySignature::Default {
+                        self.additional_params.push(AdditionalParameter::Signature(entry.signature.clone()));
+                    }
+                }
+                param => self.additional_params.push(param.clone()),
+            }
+        }
+        Some(section)
+    }
+
+    pub fn has_sections(&self) -> bool {
+        self.additional_params.iter().any(|p| matches!(p, AdditionalParameter::Conditional(_)))
+    }
+
+    /// Whether a selected section asked for the runtime not to be booted in this process.
+    pub fn skip_boot(&self) -> bool {
+        self.additional_params.iter().any(|p| matches!(p, AdditionalParameter::Skip))
+    }
+}
@@ -0,0 +1,132 @@
+use std::ffi::{OsStr, OsString};
+
+use crate::config::{AdditionalParameter, LoadConfig};
+
+#[cfg(windows)]
+const PATH_SEPARATOR: &str = ";";
+#[cfg(not(windows))]
+const PATH_SEPARATOR: &str = ":";
+
+/// A change to a single environment variable of the host process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvironmentAction<'a> {
+    Set(&'a OsStr, &'a OsStr),
+    Unset(&'a OsStr),
+    Default(&'a OsStr, &'a OsStr),
+    Prepend(&'a OsStr, &'a OsStr),
+    Append(&'a OsStr, &'a OsStr),
+}
+
+impl<'a> EnvironmentAction<'a> {
+    pub fn key(&self) -> &'a OsStr {
+        match *self {
+            EnvironmentAction::Set(key, _)
+            | EnvironmentAction::Unset(key)
+            | EnvironmentAction::Default(key, _)
+            | EnvironmentAction::Prepend(key, _)
+            | EnvironmentAction::Append(key, _) => key,
+        }
+    }
+
+    /// The value of the variable after this action, given its value before, where `None` is unset.
+    ///
+    /// Prepending or appending to an unset or empty variable sets it without a separator.
+    pub fn apply_to(&self, current: Option<&OsStr>) -> Option<OsString> {
+        let current = current.map(OsStr::to_os_string);
+        match *self {
+            EnvironmentAction::Set(_, value) => Some(value.to_os_string()),
+            EnvironmentAction::Unset(_) => None,
+            EnvironmentAction::Default(_, value) => current.or_else(|| Some(value.to_os_string())),
+            EnvironmentAction::Prepend(_, value) => match current.filter(|c| !c.is_empty()) {
+                Some(current) => {
+                    let mut joined = value.to_os_string();
+                    joined.push(PATH_SEPARATOR);
+                    joined.push(current);
+                    Some(joined)
+                }
+                None => Some(value.to_os_string()),
+            },
+            EnvironmentAction::Append(_, value) => match current.filter(|c| !c.is_empty()) {
+                Some(mut joined) => {
+                    joined.push(PATH_SEPARATOR);
+                    joined.push(value);
+                    Some(joined)
+                }
+                None => Some(value.to_os_string()),
+            },
+        }
+    }
+
+    /// Applies the action to the environment of the current process.
+    pub fn apply(&self) {
+        match self.apply_to(std::env::var_os(self.key()).as_deref()) {
+            Some(value) => std::env::set_var(self.key(), value),
+            None => std::env::remove_var(self.key()),
+        }
+    }
+}
+
+/// The values environment variables had before the configuration changed them, which are restored on drop.
+#[derive(Debug, Default)]
+pub struct EnvironmentSnapshot {
+    saved: Vec<(OsString, Option<OsString>)>,
+}
+
+impl EnvironmentSnapshot {
+    /// Restores the saved values now, rather than when the snapshot is dropped.
+    pub fn restore(mut self) {
+        self.restore_saved();
+    }
+
+    fn restore_saved(&mut self) {
+        for (key, value) in self.saved.drain(..) {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
+    }
+}
+
+impl Drop for EnvironmentSnapshot {
+    fn drop(&mut self) {
+        // Also restores when booting fails part way, before `restore` is called.
+        self.restore_saved();
+    }
+}
+
+impl LoadConfig {
+    /// Whether `restore-env` applies to the variable `key`.
+    pub fn restores_environment(&self, key: &OsStr) -> bool {
+        self.additional_params.iter().any(|p| match p {
+            AdditionalParameter::RestoreEnvironment(None) => true,
+            AdditionalParameter::RestoreEnvironment(Some(k)) => k == key,
+            _ => false
+        })
+    }
+
+    /// Applies every environment directive in order, and snapshots the prior values of those to be restored.
+    pub fn apply_environment(&self) -> EnvironmentSnapshot {
+        let mut snapshot = EnvironmentSnapshot::default();
+        for action in self.environment_actions() {
+            let key = action.key();
+            if self.restores_environment(key) && !snapshot.saved.iter().any(|(k, _)| k == key) {
+                snapshot.saved.push((key.to_os_string(), std::env::var_os(key)));
+            }
+            action.apply();
+        }
+        snapshot
+    }
+
+    /// Every environment directive, in the order they appear and are applied.
+    pub fn environment_actions(&self) -> impl Iterator<Item=EnvironmentAction<'_>> {
+        self.additional_params.iter().filter_map(|p| match p {
+            AdditionalParameter::EnvironmentVariable(k, v) => Some(EnvironmentAction::Set(k, v)),
+            AdditionalParameter::EnvironmentUnset(k) => Some(EnvironmentAction::Unset(k)),
+            AdditionalParameter::EnvironmentDefault(k, v) => Some(EnvironmentAction::Default(k, v)),
+            AdditionalParameter::EnvironmentPrepend(k, v) => Some(EnvironmentAction::Prepend(k, v)),
+            AdditionalParameter::EnvironmentAppend(k, v) => Some(EnvironmentAction::Append(k, v)),
+            _ => None
+        })
+    }
+}
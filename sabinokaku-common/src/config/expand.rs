@@ -0,0 +1,112 @@
+use std::env::current_exe;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::config::Problem;
+
+/// Locations that `${MODULE_DIR}` and `${EXE_DIR}` expand to.
+///
+/// `${ORIGIN}` is always the directory of the configuration file being parsed, and any other
+/// `${NAME}` is looked up in the environment of the host process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpansionContext {
+    pub module_dir: Option<PathBuf>,
+    pub exe_dir: Option<PathBuf>,
+}
+
+impl ExpansionContext {
+    /// The context for a module loaded from `module_path` into the current process.
+    pub fn for_module(module_path: Option<PathBuf>) -> ExpansionContext {
+        ExpansionContext {
+            module_dir: module_path.and_then(|p| p.parent().map(PathBuf::from)),
+            exe_dir: current_exe().ok().and_then(|p| p.parent().map(PathBuf::from)),
+        }
+    }
+}
+
+pub(super) struct Expander<'a> {
+    pub origin: &'a Path,
+    pub context: &'a ExpansionContext,
+}
+
+impl Expander<'_> {
+    /// Expands `${NAME}` variables and a leading `~` in `value`.
+    ///
+    /// `$$` and `$~` escape a literal `$` and `~`, and any other `$` is taken literally.
+    /// Problems are returned as warnings pointing at a subslice of `value`.
+    pub fn expand<'s>(&self, value: &'s str) -> (OsString, Vec<Problem<'s>>) {
+        let mut out = OsString::new();
+        let mut warnings = Vec::new();
+
+        let mut rest = value;
+        if let Some(after) = rest.strip_prefix('~') {
+            if after.is_empty() || after.starts_with(['/', std::path::MAIN_SEPARATOR]) {
+                match home_dir() {
+                    Some(home) => out.push(home),
+                    None => warnings.push((&value[..1], String::from("`~` used, but the home directory is unknown"))),
+                }
+                rest = after;
+            }
+        }
+
+        while let Some(start) = rest.find('$') {
+            out.push(&rest[..start]);
+            let var = &rest[start..];
+            if var.starts_with("$$") || var.starts_with("$~") {
+                out.push(&var[1..2]);
+                rest = &var[2..];
+            } else if let Some(name) = var.strip_prefix("${") {
+                let end = match name.find('}') {
+                    Some(end) => end,
+                    None => {
+                        warnings.push((var, String::from("unterminated variable, expected `}`")));
+                        out.push(var);
+                        rest = "";
+                        break;
+                    }
+                };
+                let token = &var[..end + 3];
+                match self.lookup(&name[..end]) {
+                    Some(expanded) => out.push(expanded),
+                    None => warnings.push((token, format!("undefined variable `{}`", &name[..end]))),
+                }
+                rest = &var[end + 3..];
+            } else {
+                out.push("$");
+                rest = &var[1..];
+            }
+        }
+        out.push(rest);
+
+        (out, warnings)
+    }
+
+    fn lookup(&self, name: &str) -> Option<OsString> {
+        match name {
+            "ORIGIN" if self.origin.as_os_str().is_empty() => Some(OsString::from(".")),
+            "ORIGIN" => Some(self.origin.as_os_str().to_os_string()),
+            "MODULE_DIR" => self.context.module_dir.as_ref().map(|p| p.as_os_str().to_os_string()),
+            "EXE_DIR" => self.context.exe_dir.as_ref().map(|p| p.as_os_str().to_os_string()),
+            _ => std::env::var_os(name),
+        }
+    }
+}
+
+pub(super) fn home_dir() -> Option<OsString> {
+    #[cfg(windows)]
+    let home = std::env::var_os("USERPROFILE");
+    #[cfg(not(windows))]
+    let home = std::env::var_os("HOME");
+
+    home.filter(|h| !h.is_empty())
+}
+
+/// Escapes `value` so that it expands back to itself.
+pub(super) fn escape(value: &str) -> String {
+    let escaped = value.replace('$', "$$");
+    match escaped.strip_prefix('~') {
+        Some(rest) => format!("$~{}", rest),
+        None => escaped,
+    }
+}
+
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::config::{AdditionalParameter, ConfigDiagnostic, ConfigError, ExpansionContext, Expander, LoadConfig};
+
+/// Directory next to the configuration file, whose `*.co` files are merged into it.
+const DROP_IN_DIR: &str = "kaku.d";
+
+impl LoadConfig {
+    /// Reads and parses the configuration file at `path`, merging in every `include` directive and drop-in.
+    ///
+    /// Included files and the `*.co` files in `kaku.d` next to `path` hold directives only, without a preamble.
+    /// Includes are merged in place of the `include` directive, and drop-ins are merged after the configuration
+    /// in the order of their file names.
+    ///
+    /// A [single-valued](AdditionalParameter::is_single_valued) directive is only taken once from each file, the
+    /// first time it is given there. Where several files give it, the one merged last wins, so a file overrides
+    /// what it includes before the directive, and drop-ins override the configuration.
+    pub fn load(path: &Path, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+        let input = std::fs::read_to_string(path)?;
+        LoadConfig::load_merged(path, &input, context, true)
+    }
+
+    /// Parses `input` as if it were the configuration file at `path`, which does not need to exist, merging in
+    /// every `include` directive the same as [`LoadConfig::load`]. Drop-ins are not merged, as they belong to a
+    /// configuration file deployed next to them rather than to a configuration given as a string.
+    pub fn load_str(path: &Path, input: &str, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+        LoadConfig::load_merged(path, input, context, false)
+    }
+
+    fn load_merged(path: &Path, input: &str, context: &ExpansionContext,
+                   with_drop_ins: bool) -> Result<LoadConfig, Box<dyn Error>> {
+        let mut config = LoadConfig::parse_unchecked(path.to_path_buf(), input, context)?;
+        for warning in &mut config.warnings {
+            warning.file = Some(path.to_path_buf());
+        }
+
+        let mut stack: Vec<PathBuf> = path.canonicalize().ok().into_iter().collect();
+        let params = std::mem::take(&mut config.additional_params);
+        config.additional_params = resolve(params, &mut stack, context, &mut config.warnings)?;
+
+        if with_drop_ins {
+            for drop_in in drop_ins(path)? {
+                let params = load_fragment(&drop_in, &mut stack, context, &mut config.warnings)?;
+                config.additional_params.extend(params);
+            }
+        }
+
+        merge_single_valued(&mut config.additional_params);
+        config.check_strict()
+    }
+}
+
+/// Replaces every `include` in `params` with the directives of the included file.
+///
+/// `stack` holds the canonical paths of the files currently being included, to detect cycles.
+fn resolve(params: Vec<AdditionalParameter>, stack: &mut Vec<PathBuf>, context: &ExpansionContext,
+           warnings: &mut Vec<ConfigDiagnostic>) -> Result<Vec<AdditionalParameter>, Box<dyn Error>> {
+    let mut resolved = Vec::with_capacity(params.len());
+    for param in params {
+        match param {
+            AdditionalParameter::Include(path) => resolved.extend(load_fragment(&path, stack, context, warnings)?),
+            param => resolved.push(param),
+        }
+    }
+    Ok(resolved)
+}
+
+fn load_fragment(path: &Path, stack: &mut Vec<PathBuf>, context: &ExpansionContext,
+                 warnings: &mut Vec<ConfigDiagnostic>) -> Result<Vec<AdditionalParameter>, Box<dyn Error>> {
+    let canonical = match path.canonicalize() {
+        Ok(canonical) => canonical,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(Box::new(ConfigError::MissingInclude(path.to_path_buf()))),
+        Err(e) => return Err(Box::new(e)),
+    };
+    if stack.contains(&canonical) {
+        let mut chain = stack.clone();
+        chain.push(canonical);
+        return Err(Box::new(ConfigError::IncludeCycle(chain)));
+    }
+
+    let input = std::fs::read_to_string(path)?;
+    let input = input.trim_start_matches('\u{feff}');
+    let lines: Vec<&str> = input.lines().collect();
+
+    let origin = path.parent().unwrap_or_else(|| Path::new(""));
+    let expander = Expander { origin, context };
+    let (params, fragment_warnings) = LoadConfig::parse_additional(&expander, &lines, 1);
+    warnings.extend(fragment_warnings.into_iter().map(|warning| ConfigDiagnostic { file: Some(path.to_path_buf()), ..warning }));
+
+    stack.push(canonical);
+    let params = resolve(params, stack, context, warnings);
+    stack.pop();
+    params
+}
+
+/// The `*.co` files in the drop-in directory next to `path`, sorted by file name.
+fn drop_ins(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut dir = PathBuf::from(path.parent().unwrap_or_else(|| Path::new("")));
+    dir.push(DROP_IN_DIR);
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let mut drop_ins = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() && path.extension() == Some(OsStr::new("co")) {
+            drop_ins.push(path);
+        }
+    }
+    drop_ins.sort();
+    Ok(drop_ins)
+}
+
+/// Keeps only the last of each single-valued directive, so that later files override earlier ones. Each file
+/// already gives each of them at most once.
+fn merge_single_valued(params: &mut Vec<AdditionalParameter>) {
+    let mut seen = HashSet::new();
+    let mut merged: Vec<AdditionalParameter> = params.drain(..)
+        .rev()
+        .filter(|param| !param.is_single_valued() || seen.insert(std::mem::discriminant(param)))
+        .collect();
+    merged.reverse();
+    *params = merged;
+}
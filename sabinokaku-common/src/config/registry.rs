@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::condition::exe_match;
+use crate::config::user_config_dir;
+
+/// A mapping from host executables to the configuration file to use for them.
+///
+/// Each line of a registry file is `EXE = CONFIG`, where `EXE` is a path or glob matched against the
+/// canonical path of the host executable, and `CONFIG` is the path to its configuration file, relative to
+/// the registry file. Empty lines, lines starting with `#`, and lines without `=` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Registry {
+    pub mappings: Vec<RegistryMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryMapping {
+    /// Globs without a path separator only match the file name of the executable.
+    pub exe: String,
+    pub config: PathBuf,
+}
+
+impl Registry {
+    /// The per-user registry, followed by the system-wide registry.
+    pub fn default_paths() -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        if let Some(config_dir) = user_config_dir() {
+            let mut path = config_dir;
+            path.push("sabinokaku");
+            path.push("registry");
+            paths.push(path);
+        }
+
+        #[cfg(windows)]
+        if let Some(program_data) = std::env::var_os("PROGRAMDATA").filter(|dir| !dir.is_empty()) {
+            let mut path = PathBuf::from(program_data);
+            path.push("sabinokaku");
+            path.push("registry");
+            paths.push(path);
+        }
+
+        #[cfg(not(windows))]
+        paths.push(PathBuf::from("/etc/sabinokaku/registry"));
+
+        paths
+    }
+
+    pub fn parse(path: &Path, input: &str) -> Registry {
+        let origin = path.parent().unwrap_or_else(|| Path::new(""));
+        let mappings = input.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(exe, config)| {
+                let mut buf = PathBuf::from(origin);
+                buf.push(config.trim());
+                RegistryMapping { exe: exe.trim().to_string(), config: buf }
+            })
+            .collect();
+        Registry { mappings }
+    }
+
+    /// Reads every registry in `paths` that exists, in order, or `None` if none of them exist.
+    pub fn load(paths: &[PathBuf]) -> Option<Registry> {
+        let mut registry: Option<Registry> = None;
+        for path in paths {
+            if let Ok(input) = std::fs::read_to_string(path) {
+                registry.get_or_insert_with(Registry::default).mappings.extend(Registry::parse(path, &input).mappings);
+            }
+        }
+        registry
+    }
+
+    /// The configuration file of the first mapping that matches `exe`.
+    pub fn lookup(&self, exe: &Path) -> Option<&Path> {
+        self.mappings.iter()
+            .find(|mapping| exe_match(&mapping.exe, exe))
+            .map(|mapping| mapping.config.as_path())
+    }
+}
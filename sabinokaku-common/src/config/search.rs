@@ -0,0 +1,96 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::config::expand::home_dir;
+
+/// Environment variable pointing at an explicit configuration file, which skips the search entirely.
+pub const CONFIG_ENV_VAR: &str = "SABINOKAKU_CONFIG";
+
+/// File names that are looked for in every search directory, in order of preference.
+const CONFIG_FILE_NAMES: [&str; 2] = ["kaku.co", "kaku.toml"];
+
+/// How many parent directories of the executable's directory are searched at most.
+pub const MAX_PARENT_DEPTH: usize = 3;
+
+/// Every path a configuration file is looked for at, in order of preference.
+///
+/// These are the directories of the module and the executable, then `<exe-name>.co` in the per-user
+/// configuration directory, then up to [`MAX_PARENT_DEPTH`] parent directories of the executable's directory.
+///
+/// Anyone who can write to a parent directory could make every executable below it boot their code, so the walk
+/// stops before `home_dir`, the root, or a directory anyone can write to.
+pub fn config_candidates(module_path: Option<&Path>, exe_path: Option<&Path>, user_config_dir: Option<&Path>,
+                         home_dir: Option<&Path>) -> Vec<PathBuf> {
+    fn push_dir(candidates: &mut Vec<PathBuf>, dir: &Path) {
+        for name in CONFIG_FILE_NAMES {
+            let path = dir.join(name);
+            if !candidates.contains(&path) {
+                candidates.push(path);
+            }
+        }
+    }
+
+    let mut candidates = Vec::new();
+
+    if let Some(module_dir) = module_path.and_then(Path::parent) {
+        push_dir(&mut candidates, module_dir);
+    }
+
+    let exe_dir = exe_path.and_then(Path::parent);
+    if let Some(exe_dir) = exe_dir {
+        push_dir(&mut candidates, exe_dir);
+    }
+
+    if let (Some(config_dir), Some(exe_name)) = (user_config_dir, exe_path.and_then(Path::file_stem)) {
+        let mut path = PathBuf::from(config_dir);
+        path.push("sabinokaku");
+        let mut file_name = OsString::from(exe_name);
+        file_name.push(".co");
+        path.push(file_name);
+        candidates.push(path);
+    }
+
+    for dir in exe_dir.into_iter().flat_map(Path::ancestors).skip(1).take(MAX_PARENT_DEPTH) {
+        if dir.parent().is_none() || Some(dir) == home_dir || is_world_writable(dir) {
+            break;
+        }
+        push_dir(&mut candidates, dir);
+    }
+
+    candidates
+}
+
+/// Whether a registry is the only source of configuration meant for the host process, which is the case when the
+/// module does not sit in the directory of the host executable, or below it, such as when it is preloaded into every
+/// process. Otherwise the module was deployed with the host, and a missing configuration is an error.
+pub fn registry_only(module_path: Option<&Path>, exe_path: Option<&Path>) -> bool {
+    match (module_path.and_then(Path::parent), exe_path.and_then(Path::parent)) {
+        (Some(module_dir), Some(exe_dir)) => !module_dir.starts_with(exe_dir),
+        _ => false
+    }
+}
+
+/// Whether anyone can create files in `dir`, such as `/tmp`. Directories that can not be read are not.
+#[cfg(unix)]
+fn is_world_writable(dir: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    matches!(dir.metadata(), Ok(metadata) if metadata.permissions().mode() & 0o002 != 0)
+}
+
+/// Windows has no single permission bit for this, and its ACLs are not checked.
+#[cfg(not(unix))]
+fn is_world_writable(_dir: &Path) -> bool {
+    false
+}
+
+/// `$XDG_CONFIG_HOME`, or `~/.config` if it is not set. On Windows, this is `%APPDATA%` instead.
+pub fn user_config_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    return std::env::var_os("APPDATA").filter(|dir| !dir.is_empty()).map(PathBuf::from);
+
+    #[cfg(not(windows))]
+    return std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home_dir().map(|home| Path::new(&home).join(".config")));
+}
@@ -0,0 +1,441 @@
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use netcorehost::pdcstring::PdCString;
+use serde::Deserialize;
+
+use crate::config::{parse_entry, parse_entry_path, parse_signature, split_short_entry, AdditionalParameter, BootMode, Condition,
+                    ConditionalSection, ConfigDiagnostic, ConfigError, EntrySignature, ExitPolicy, ExpansionContext,
+                    Expander, FailurePolicy, LoadConfig, LogSink, VulkanInitParams};
+use crate::services::LogLevel;
+
+/// The structured format is plain TOML, so the magic is a comment to keep the document valid.
+pub(super) fn is_structured_magic(line: &str) -> bool {
+    matches!(line.strip_prefix('#').map(str::trim), Some("kaku_t"))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    #[serde(default)]
+    strict: bool,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    forward_args: bool,
+    on_failure: Option<String>,
+    on_return: Option<String>,
+    boot: Option<String>,
+    on_exit: Option<String>,
+    log: Option<Log>,
+    /// Where to write the boot report, equivalent to the `report` directive.
+    report: Option<String>,
+    entry: Option<Entry>,
+    /// The application assembly to run instead of calling `entry`, equivalent to the `kaku_x` preamble.
+    app: Option<String>,
+    #[serde(default)]
+    runtime: Runtime,
+    #[serde(default)]
+    env: toml::value::Table,
+    restore_env: Option<toml::Value>,
+    #[serde(default)]
+    properties: toml::value::Table,
+    vulkan: Option<Vulkan>,
+    /// Values of custom directives, equivalent to `x-NAME` directives in the line based formats.
+    #[serde(default)]
+    custom: toml::value::Table,
+    #[serde(default)]
+    entries: Vec<Entries>,
+    #[serde(default)]
+    when: Vec<When>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    short: Option<String>,
+    assembly: Option<String>,
+    #[serde(rename = "type")]
+    type_name: Option<String>,
+    method: Option<String>,
+    delegate: Option<String>,
+    #[serde(default)]
+    unmanaged: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Runtime {
+    config: Option<String>,
+    hostfxr: Option<String>,
+    dotnetroot: Option<String>,
+}
+
+/// The `[log]` table, equivalent to the `log` and `log-to` directives.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Log {
+    level: Option<String>,
+    #[serde(default)]
+    stderr: bool,
+    #[serde(default)]
+    syslog: bool,
+    file: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Vulkan {
+    loader_version: u32,
+    entry: String,
+}
+
+/// An `[[entries]]` table, equivalent to an `entry` directive in the line based formats. The options of the directive
+/// are keys of their own, and `path` is taken as is.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entries {
+    path: String,
+    order: Option<i32>,
+    #[serde(rename = "continue")]
+    continue_on_failure: Option<bool>,
+    delegate: Option<String>,
+    #[serde(default)]
+    unmanaged: bool,
+}
+
+/// A `[[when]]` section, equivalent to a `when` block in the line based formats.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct When {
+    exe: Option<String>,
+    arg: Option<String>,
+    has_env: Option<String>,
+    uid: Option<u32>,
+    #[serde(default)]
+    skip: bool,
+    entry: Option<String>,
+    #[serde(default)]
+    env: toml::value::Table,
+}
+
+/// The signature of an entry point given by its `delegate` and `unmanaged` keys, if any.
+fn entry_signature(delegate: Option<String>, unmanaged: bool) -> Result<Option<EntrySignature>, String> {
+    match (delegate, unmanaged) {
+        (None, false) => Ok(None),
+        (None, true) => Ok(Some(EntrySignature::UnmanagedCallersOnly)),
+        (Some(delegate), false) => match parse_signature(&delegate) {
+            Some(EntrySignature::Delegate(delegate)) => Ok(Some(EntrySignature::Delegate(delegate))),
+            _ => Err(format!("invalid delegate type `{}`", delegate)),
+        },
+        (Some(_), true) => Err(String::from("expected either `delegate` or `unmanaged`, not both")),
+    }
+}
+
+/// Points at the header of `[table]`, since the TOML deserializer does not keep spans for values.
+fn table_warning(input: &str, table: &str, reason: impl Into<String>) -> ConfigDiagnostic {
+    let header = format!("[{}]", table);
+    match input.lines().enumerate().find(|(_, line)| line.trim() == header) {
+        Some((line_no, line)) => ConfigDiagnostic::new(line_no + 1, line, line.trim_start(), reason),
+        None => ConfigDiagnostic::new(1, "", "", reason)
+    }
+}
+
+/// Points at the header of the `index`th `[[table]]` in an array of tables.
+fn array_warning(input: &str, table: &str, index: usize, reason: impl Into<String>) -> ConfigDiagnostic {
+    let header = format!("[[{}]]", table);
+    match input.lines().enumerate().filter(|(_, line)| line.trim() == header).nth(index) {
+        Some((line_no, line)) => ConfigDiagnostic::new(line_no + 1, line, line.trim_start(), reason),
+        None => ConfigDiagnostic::new(1, "", "", reason)
+    }
+}
+
+fn table_diagnostic(input: &str, table: &str, reason: impl Into<String>) -> ConfigError {
+    ConfigError::InvalidConfig(table_warning(input, table, reason))
+}
+
+fn syntax_diagnostic(input: &str, error: toml::de::Error) -> ConfigError {
+    let (line, column) = error.line_col().unwrap_or((0, 0));
+    ConfigError::InvalidConfig(ConfigDiagnostic {
+        file: None,
+        line: line + 1,
+        column: column + 1,
+        text: input.lines().nth(line).unwrap_or_default().to_string(),
+        reason: error.to_string(),
+    })
+}
+
+/// An `env` value is either a string to set, or a table with one of `default`, `prepend` or `append`, or `unset = true`.
+fn env_param(expander: &Expander, key: String, value: &toml::Value) -> Result<(AdditionalParameter, Vec<String>), String> {
+    let key = OsString::from(key);
+    let invalid = || format!("expected a string, or one of `default`, `prepend`, `append` or `unset` for `{}`", key.to_string_lossy());
+
+    let (mode, value) = match value {
+        toml::Value::String(value) => ("set", value.as_str()),
+        toml::Value::Table(table) if table.len() == 1 => match table.iter().next() {
+            Some((mode, toml::Value::Boolean(true))) if mode == "unset" => return Ok((AdditionalParameter::EnvironmentUnset(key), vec![])),
+            Some((mode, toml::Value::String(value))) => (mode.as_str(), value.as_str()),
+            _ => return Err(invalid())
+        },
+        _ => return Err(invalid())
+    };
+
+    let (value, problems) = expander.expand(value);
+    let problems = problems.into_iter().map(|(_, reason)| reason).collect();
+    let param = match mode {
+        "set" => AdditionalParameter::EnvironmentVariable(key, value),
+        "default" => AdditionalParameter::EnvironmentDefault(key, value),
+        "prepend" => AdditionalParameter::EnvironmentPrepend(key, value),
+        "append" => AdditionalParameter::EnvironmentAppend(key, value),
+        _ => return Err(invalid())
+    };
+    Ok((param, problems))
+}
+
+pub(super) fn parse_structured(root: PathBuf, input: &str, context: &ExpansionContext) -> Result<LoadConfig, Box<dyn Error>> {
+    let document: Document = toml::from_str(input).map_err(|e| syntax_diagnostic(input, e))?;
+    let root = root.parent().ok_or_else(|| ConfigError::MissingConfig(vec![root.clone()]))?;
+    let expander = Expander { origin: root, context };
+
+    let mut warnings = Vec::new();
+    let mut expand = |table: &str, value: &str| {
+        let (expanded, problems) = expander.expand(value);
+        warnings.extend(problems.into_iter().map(|(_, reason)| table_warning(input, table, reason)));
+        expanded
+    };
+
+    let signature = match &document.entry {
+        Some(entry) => entry_signature(entry.delegate.clone(), entry.unmanaged)
+            .map_err(|reason| table_diagnostic(input, "entry", reason))?,
+        None => None,
+    };
+
+    let (assembly_fname, entry_type, entry_fn) = match (document.entry, &document.app) {
+        // The type name of an application is its name, which is only known once its path is expanded.
+        (None, Some(app)) => (app.clone(), String::new(), String::from("Main")),
+        (Some(Entry { short: Some(short), assembly: None, type_name: None, method: None, .. }), None) => {
+            let (asm, entry_cls, entry_fn) = split_short_entry(&short)
+                .ok_or_else(|| table_diagnostic(input, "entry",
+                    format!("expected `short` of the form `AssemblyName::QualifiedClassName!EntryFunction`, was `{}`", short)))?;
+            (format!("{}.dll", asm), format!("{}, {}", entry_cls, asm), entry_fn.to_string())
+        }
+        (Some(Entry { short: None, assembly: Some(assembly), type_name: Some(type_name), method: Some(method), .. }), None) => {
+            (assembly, type_name, method)
+        }
+        (Some(_), None) => return Err(Box::new(table_diagnostic(input, "entry",
+            "expected either `short`, or all of `assembly`, `type` and `method`"))),
+        _ => return Err(Box::new(table_diagnostic(input, "entry", "expected either an `[entry]` table, or `app`")))
+    };
+
+    // Like the short format, the runtime configuration sits next to the assembly unless specified.
+    let runtime_config = match document.runtime.config {
+        Some(runtime_config) => runtime_config,
+        None => {
+            let stem = Path::new(&assembly_fname).with_extension("");
+            format!("{}.runtimeconfig.json", stem.display())
+        }
+    };
+
+    let mut runtime_config_path = PathBuf::from(root);
+    runtime_config_path.push(expand("runtime", &runtime_config));
+
+    let mut assembly_fname_path = PathBuf::from(root);
+    assembly_fname_path.push(expand("entry", &assembly_fname));
+
+    let mut additional = Vec::new();
+    let mut entry_type = PdCString::from_str(&entry_type)?;
+    if document.app.is_some() {
+        additional.push(AdditionalParameter::Application);
+        let app_name = assembly_fname_path.file_stem()
+            .ok_or_else(|| table_diagnostic(input, "app", "expected the path to the application assembly for `app`"))?;
+        entry_type = PdCString::from_os_str(app_name)?;
+    }
+    additional.extend(signature.map(AdditionalParameter::Signature));
+
+    if document.strict {
+        additional.push(AdditionalParameter::Strict);
+    }
+
+    let policy = |key: &str, policy: String| policy.parse::<FailurePolicy>()
+        .map_err(|_| table_diagnostic(input, key, format!("expected `ignore`, `log`, `exit [CODE]` or `abort` for `{}`", key)));
+    if let Some(on_failure) = document.on_failure {
+        additional.push(AdditionalParameter::OnFailure(policy("on_failure", on_failure)?));
+    }
+    if let Some(on_return) = document.on_return {
+        additional.push(AdditionalParameter::OnReturn(policy("on_return", on_return)?));
+    }
+    if let Some(boot) = document.boot {
+        let mode = boot.parse::<BootMode>()
+            .map_err(|_| table_diagnostic(input, "boot", "expected `async`, `blocking`, `ready [TIMEOUT_MS]` or `main-thread` for `boot`"))?;
+        additional.push(AdditionalParameter::Boot(mode));
+    }
+    if let Some(on_exit) = document.on_exit {
+        let policy = on_exit.parse::<ExitPolicy>()
+            .map_err(|_| table_diagnostic(input, "on_exit", "expected `detach` or `wait [TIMEOUT_MS]` for `on_exit`"))?;
+        additional.push(AdditionalParameter::OnExit(policy));
+    }
+
+    if let Some(log) = document.log {
+        if let Some(level) = log.level {
+            let level = LogLevel::parse_filter(&level)
+                .ok_or_else(|| table_diagnostic(input, "log", "expected `off`, `error`, `warn`, `info`, `debug` or `trace` for `level`"))?;
+            additional.push(AdditionalParameter::Log(level));
+        }
+        if log.stderr {
+            additional.push(AdditionalParameter::LogTo(LogSink::Stderr));
+        }
+        if log.syslog {
+            additional.push(AdditionalParameter::LogTo(LogSink::Syslog));
+        }
+        if let Some(file) = log.file {
+            let mut buf = PathBuf::from(root);
+            buf.push(expand("log", &file));
+            additional.push(AdditionalParameter::LogTo(LogSink::File(buf)));
+        }
+    }
+
+    if let Some(report) = document.report {
+        let mut buf = PathBuf::from(root);
+        buf.push(expand("report", &report));
+        additional.push(AdditionalParameter::Report(buf));
+    }
+
+    // Includes come first, so that the document itself overrides anything it includes.
+    for include in document.include {
+        let mut buf = PathBuf::from(root);
+        buf.push(expand("include", &include));
+        additional.push(AdditionalParameter::Include(buf));
+    }
+
+    if let Some(hostfxr) = document.runtime.hostfxr {
+        let mut buf = PathBuf::from(root);
+        buf.push(expand("runtime", &hostfxr));
+        additional.push(AdditionalParameter::Hostfxr(buf));
+    }
+
+    if let Some(dotnetroot) = document.runtime.dotnetroot {
+        let mut buf = PathBuf::from(root);
+        buf.push(expand("runtime", &dotnetroot));
+        additional.push(AdditionalParameter::DotNetRoot(buf));
+    }
+
+    for arg in document.args {
+        additional.push(AdditionalParameter::Argument(expand("args", &arg)));
+    }
+    if document.forward_args {
+        additional.push(AdditionalParameter::ForwardArguments);
+    }
+
+    for (name, value) in document.properties {
+        let value = match value {
+            toml::Value::String(value) => value,
+            // Runtime properties are always strings, but booleans and numbers are accepted for switches such as `System.GC.Server`.
+            toml::Value::Boolean(_) | toml::Value::Integer(_) => value.to_string(),
+            _ => return Err(Box::new(table_diagnostic(input, "properties", format!("expected a string value for `{}`", name))))
+        };
+        additional.push(AdditionalParameter::RuntimeProperty(OsString::from(name), expand("properties", &value)));
+    }
+
+    // A custom directive is either a single string, or a list of them to give it several times.
+    for (name, value) in document.custom {
+        let values = match value {
+            toml::Value::String(value) => vec![value],
+            toml::Value::Array(values) => values.into_iter()
+                .map(|value| value.as_str().map(String::from))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| table_diagnostic(input, "custom", format!("expected a list of strings for `{}`", name)))?,
+            _ => return Err(Box::new(table_diagnostic(input, "custom", format!("expected a string, or a list of strings for `{}`", name))))
+        };
+        for value in values {
+            additional.push(AdditionalParameter::Custom(name.clone(), expand("custom", &value)));
+        }
+    }
+
+    for (key, value) in document.env {
+        let (param, problems) = env_param(&expander, key, &value).map_err(|reason| table_diagnostic(input, "env", reason))?;
+        warnings.extend(problems.into_iter().map(|reason| table_warning(input, "env", reason)));
+        additional.push(param);
+    }
+
+    // `restore_env` is either `true` to restore every variable, or a list of the variables to restore.
+    match document.restore_env {
+        None | Some(toml::Value::Boolean(false)) => {}
+        Some(toml::Value::Boolean(true)) => additional.push(AdditionalParameter::RestoreEnvironment(None)),
+        Some(toml::Value::Array(keys)) => for key in keys {
+            let key = key.as_str()
+                .ok_or_else(|| table_diagnostic(input, "restore_env", "expected the names of variables in `restore_env`"))?;
+            additional.push(AdditionalParameter::RestoreEnvironment(Some(OsString::from(key))));
+        },
+        Some(_) => return Err(Box::new(table_diagnostic(input, "restore_env",
+            "expected `true`, or a list of variable names for `restore_env`")))
+    }
+
+    if let Some(vulkan) = document.vulkan {
+        let entry = vulkan.entry.parse()
+            .map_err(|_| table_diagnostic(input, "vulkan", format!("unknown Vulkan entry point `{}`", vulkan.entry)))?;
+        additional.push(AdditionalParameter::Vulkan(VulkanInitParams { loader_version: vulkan.loader_version, entry }));
+    }
+
+    for (index, entries) in document.entries.into_iter().enumerate() {
+        let (mut entry, problems) = parse_entry_path(&expander, &entries.path)
+            .map_err(|(_, reason)| ConfigError::InvalidConfig(array_warning(input, "entries", index, reason)))?;
+        warnings.extend(problems.into_iter().map(|(_, reason)| array_warning(input, "entries", index, reason)));
+        entry.order = entries.order.unwrap_or(0);
+        entry.continue_on_failure = entries.continue_on_failure.unwrap_or(false);
+        let signature = entry_signature(entries.delegate, entries.unmanaged)
+            .map_err(|reason| ConfigError::InvalidConfig(array_warning(input, "entries", index, reason)))?;
+        entry.signature = signature.unwrap_or(EntrySignature::Default);
+        additional.push(AdditionalParameter::Entry(entry));
+    }
+
+    for (index, when) in document.when.into_iter().enumerate() {
+        let mut section = ConditionalSection { conditions: Vec::new(), params: Vec::new() };
+        section.conditions.extend(when.exe.map(Condition::Exe));
+        section.conditions.extend(when.arg.map(Condition::Arg));
+        section.conditions.extend(when.has_env.map(|name| Condition::HasEnv(OsString::from(name))));
+        section.conditions.extend(when.uid.map(Condition::Uid));
+        if section.conditions.is_empty() {
+            warnings.push(array_warning(input, "when", index, "expected a condition in `[[when]]`"));
+        }
+
+        for (key, value) in when.env {
+            let (param, problems) = env_param(&expander, key, &value)
+                .map_err(|reason| ConfigError::InvalidConfig(array_warning(input, "when", index, reason)))?;
+            warnings.extend(problems.into_iter().map(|reason| array_warning(input, "when", index, reason)));
+            section.params.push(param);
+        }
+
+        if let Some(entry) = when.entry {
+            match parse_entry(&expander, &entry) {
+                Ok((entry, _)) if entry.order != 0 || entry.continue_on_failure => {
+                    warnings.push(array_warning(input, "when", index,
+                        "`order` and `continue` are not allowed for `entry` inside `when`"));
+                }
+                Ok((entry, problems)) => {
+                    warnings.extend(problems.into_iter().map(|(_, reason)| array_warning(input, "when", index, reason)));
+                    section.params.push(AdditionalParameter::EntryOverride(entry));
+                }
+                Err((_, reason)) => return Err(Box::new(ConfigError::InvalidConfig(array_warning(input, "when", index, reason))))
+            }
+        }
+
+        if when.skip {
+            section.params.push(AdditionalParameter::Skip);
+        }
+        additional.push(AdditionalParameter::Conditional(section));
+    }
+
+    let mut config = LoadConfig::new(
+        PdCString::from_os_str(runtime_config_path.as_os_str())?,
+        PdCString::from_os_str(assembly_fname_path.as_os_str())?,
+        entry_type,
+        PdCString::from_str(&entry_fn)?,
+        additional
+    );
+    config.warnings = warnings;
+    Ok(config)
+}
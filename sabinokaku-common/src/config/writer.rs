@@ -0,0 +1,242 @@
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use netcorehost::pdcstring::PdCStr;
+
+use crate::config::{AdditionalParameter, Condition, ConfigError, ConfigFormat, EntryPoint, EntrySignature, LoadConfig, LogSink};
+use crate::config::expand::escape;
+
+impl LoadConfig {
+    /// Writes the configuration back out as a canonical `kaku_l` or `kaku_s` file, or a `kaku_x` file
+    /// regardless of `format` if it runs an application.
+    ///
+    /// `root` is the path the file will be written to, and paths under its parent directory are made
+    /// relative to it, so that the output parses back into the same configuration with [`LoadConfig::try_parse`].
+    /// Values are written escaped, so variables are never introduced by writing.
+    pub fn write(&self, root: &Path, format: ConfigFormat) -> Result<String, ConfigError> {
+        let root = root.parent().ok_or_else(|| ConfigError::MissingConfig(vec![root.to_path_buf()]))?;
+        let mut out = String::new();
+
+        match format {
+            _ if self.is_application() => {
+                out.push_str("kaku_x\n");
+                push_line(&mut out, &self.app_entry(root)?)?;
+            }
+            ConfigFormat::Long => {
+                out.push_str("kaku_l\n");
+                push_line(&mut out, &relative_path(root, &pdcstr_path(&self.runtime_config))?)?;
+                push_line(&mut out, &relative_path(root, &pdcstr_path(&self.entry_assembly))?)?;
+                push_line(&mut out, &pdcstr_text(&self.type_name)?)?;
+                push_line(&mut out, &pdcstr_text(&self.entry_method)?)?;
+            }
+            ConfigFormat::Short => {
+                out.push_str("kaku_s\n");
+                push_line(&mut out, &self.short_entry(root)?)?;
+            }
+        }
+
+        write_params(&mut out, root, &self.additional_params)?;
+        Ok(out)
+    }
+
+    /// `kaku_x` can only express an application whose runtime configuration sits next to it, and shares its name.
+    fn app_entry(&self, root: &Path) -> Result<String, ConfigError> {
+        let unrepresentable = || ConfigError::Unrepresentable(String::from("application is not expressible in `kaku_x`"));
+
+        let app_path = pdcstr_path(&self.entry_assembly);
+        if pdcstr_path(&self.runtime_config) != app_path.with_extension("runtimeconfig.json")
+            || Some(pdcstr_path(&self.type_name).as_os_str()) != app_path.file_stem()
+            || pdcstr_text(&self.entry_method)? != "Main" {
+            return Err(unrepresentable());
+        }
+        relative_path(root, &app_path)
+    }
+
+    /// The short format can only express an assembly and runtime configuration that sit next to `kaku.co`,
+    /// and share the assembly's name.
+    fn short_entry(&self, root: &Path) -> Result<String, ConfigError> {
+        let unrepresentable = || ConfigError::Unrepresentable(String::from("entry point is not expressible in the short format"));
+
+        let type_name = pdcstr_text(&self.type_name)?;
+        let (entry_cls, asm) = type_name.rsplit_once(", ").ok_or_else(unrepresentable)?;
+        if asm.contains(['/', '\\']) || entry_cls.contains("::") || asm.contains("::") {
+            return Err(unrepresentable());
+        }
+
+        let mut runtime_config_path = PathBuf::from(root);
+        runtime_config_path.push(format!("{}.runtimeconfig.json", asm));
+
+        let mut assembly_fname_path = PathBuf::from(root);
+        assembly_fname_path.push(format!("{}.dll", asm));
+
+        if pdcstr_path(&self.runtime_config) != runtime_config_path.as_os_str()
+            || pdcstr_path(&self.entry_assembly) != assembly_fname_path.as_os_str() {
+            return Err(unrepresentable());
+        }
+
+        Ok(format!("{}::{}!{}", asm, entry_cls, pdcstr_text(&self.entry_method)?))
+    }
+}
+
+fn write_params(out: &mut String, root: &Path, params: &[AdditionalParameter]) -> Result<(), ConfigError> {
+    for param in params {
+        let line = match param {
+            AdditionalParameter::EnvironmentVariable(k, v) => env_line("env", k, Some(v))?,
+            AdditionalParameter::EnvironmentUnset(k) => env_line("env-unset", k, None)?,
+            AdditionalParameter::EnvironmentDefault(k, v) => env_line("env-default", k, Some(v))?,
+            AdditionalParameter::EnvironmentPrepend(k, v) => env_line("env-prepend", k, Some(v))?,
+            AdditionalParameter::EnvironmentAppend(k, v) => env_line("env-append", k, Some(v))?,
+            AdditionalParameter::RestoreEnvironment(Some(k)) => env_line("restore-env", k, None)?,
+            AdditionalParameter::RestoreEnvironment(None) => String::from("restore-env"),
+            AdditionalParameter::Hostfxr(p) => format!("hostfxr {}", relative_path(root, p)?),
+            AdditionalParameter::DotNetRoot(p) => format!("dotnetroot {}", relative_path(root, p)?),
+            AdditionalParameter::Vulkan(vulkan) => format!("vulkan {} {}", vulkan.loader_version, vulkan.entry),
+            AdditionalParameter::Strict => String::from("strict"),
+            AdditionalParameter::ForwardArguments => String::from("forward-args"),
+            AdditionalParameter::OnFailure(policy) => format!("on-failure {}", policy),
+            AdditionalParameter::OnReturn(policy) => format!("on-return {}", policy),
+            // Written as the preamble.
+            AdditionalParameter::Application => continue,
+            AdditionalParameter::Conditional(section) => {
+                if section.conditions.is_empty() {
+                    return Err(ConfigError::Unrepresentable(String::from("`when` section has no conditions")));
+                }
+                for condition in &section.conditions {
+                    check_condition(condition)?;
+                }
+                push_line(out, &section.to_string())?;
+                write_params(out, root, &section.params)?;
+                String::from("end")
+            }
+            AdditionalParameter::Entry(entry) | AdditionalParameter::EntryOverride(entry) => {
+                let mut line = format!("entry {}", entry_path(root, entry)?);
+                if entry.order != 0 {
+                    let _ = write!(line, " order={}", entry.order);
+                }
+                if entry.continue_on_failure {
+                    line.push_str(" continue");
+                }
+                match &entry.signature {
+                    EntrySignature::Default => {}
+                    EntrySignature::UnmanagedCallersOnly => line.push_str(" unmanaged"),
+                    EntrySignature::Delegate(delegate) => {
+                        let delegate = pdcstr_text(delegate)?;
+                        // Written last, as it takes the rest of the line.
+                        if delegate.is_empty() || delegate == "unmanaged" {
+                            return Err(ConfigError::Unrepresentable(format!("delegate type `{}` is not expressible as an `entry` option", delegate)));
+                        }
+                        let _ = write!(line, " delegate={}", delegate);
+                    }
+                }
+                line
+            }
+            AdditionalParameter::Signature(EntrySignature::Default) => continue,
+            AdditionalParameter::Signature(EntrySignature::UnmanagedCallersOnly) => String::from("delegate unmanaged"),
+            AdditionalParameter::Signature(EntrySignature::Delegate(delegate)) => {
+                let delegate = pdcstr_text(delegate)?;
+                if delegate.is_empty() || delegate == "unmanaged" {
+                    return Err(ConfigError::Unrepresentable(format!("delegate type `{}` is not expressible", delegate)));
+                }
+                format!("delegate {}", delegate)
+            }
+            AdditionalParameter::Skip => String::from("skip"),
+            AdditionalParameter::Include(p) => format!("include {}", relative_path(root, p)?),
+            AdditionalParameter::RuntimeProperty(k, v) => env_line("property", k, Some(v))?,
+            AdditionalParameter::Argument(arg) if arg.is_empty() => {
+                return Err(ConfigError::Unrepresentable(String::from("`arg` is empty")));
+            }
+            AdditionalParameter::Argument(arg) => format!("arg {}", escape(&os_text(arg)?)),
+            AdditionalParameter::Custom(k, _) if k.is_empty() || k.contains(' ') => {
+                return Err(ConfigError::Unrepresentable(format!("custom directive name `{}` is empty, or contains a space", k)));
+            }
+            AdditionalParameter::Custom(k, v) if v.is_empty() => format!("x-{}", k),
+            AdditionalParameter::Custom(k, v) => format!("x-{} {}", k, escape(&os_text(v)?)),
+            AdditionalParameter::Log(None) => String::from("log off"),
+            AdditionalParameter::Log(Some(level)) => format!("log {}", level),
+            AdditionalParameter::LogTo(LogSink::Stderr) => String::from("log-to stderr"),
+            AdditionalParameter::LogTo(LogSink::Syslog) => String::from("log-to syslog"),
+            AdditionalParameter::LogTo(LogSink::File(p)) => format!("log-to file {}", relative_path(root, p)?),
+            AdditionalParameter::Report(p) => format!("report {}", relative_path(root, p)?),
+            AdditionalParameter::Boot(mode) => format!("boot {}", mode),
+            AdditionalParameter::OnExit(policy) => format!("on-exit {}", policy),
+        };
+        push_line(out, &line)?;
+    }
+    Ok(())
+}
+
+/// Writes a `KEY=VALUE` directive, or just `KEY` if there is no value.
+fn env_line(directive: &str, k: &OsStr, v: Option<&OsStr>) -> Result<String, ConfigError> {
+    let k = os_text(k)?;
+    if k.is_empty() || k.contains(['=', ' ']) {
+        return Err(ConfigError::Unrepresentable(format!("`{}` name `{}` is empty, or contains `=` or a space", directive, k)));
+    }
+    match v {
+        Some(v) => Ok(format!("{} {}={}", directive, k, escape(&os_text(v)?))),
+        None => Ok(format!("{} {}", directive, k)),
+    }
+}
+
+/// Conditions are separated by spaces, so their values can neither be empty nor contain one.
+fn check_condition(condition: &Condition) -> Result<(), ConfigError> {
+    let value = match condition {
+        Condition::Exe(glob) | Condition::Arg(glob) => glob.clone(),
+        Condition::HasEnv(name) => os_text(name)?,
+        Condition::Uid(_) => return Ok(()),
+    };
+    if value.is_empty() || value.contains(' ') {
+        return Err(ConfigError::Unrepresentable(format!("condition `{}` is empty, or contains a space", condition)));
+    }
+    Ok(())
+}
+
+/// Entry points are written as `AssemblyPath::QualifiedClassName!EntryFunction`, which requires the
+/// assembly to be a `.dll` named the same as in the qualified class name.
+fn entry_path(root: &Path, entry: &EntryPoint) -> Result<String, ConfigError> {
+    let unrepresentable = || ConfigError::Unrepresentable(String::from("entry point is not expressible as `entry`"));
+
+    let assembly = pdcstr_text(&entry.assembly)?;
+    let asm_path = assembly.strip_suffix(".dll").ok_or_else(unrepresentable)?;
+    let asm = Path::new(asm_path).file_name().and_then(OsStr::to_str).ok_or_else(unrepresentable)?;
+
+    let type_name = pdcstr_text(&entry.type_name)?;
+    let entry_cls = type_name.strip_suffix(&format!(", {}", asm)).ok_or_else(unrepresentable)?;
+    if entry_cls.contains("::") || asm_path.contains("::") {
+        return Err(unrepresentable());
+    }
+
+    Ok(format!("{}::{}!{}", relative_path(root, Path::new(asm_path))?, entry_cls, pdcstr_text(&entry.method)?))
+}
+
+fn push_line(out: &mut String, line: &str) -> Result<(), ConfigError> {
+    if line.contains(['\n', '\r']) {
+        return Err(ConfigError::Unrepresentable(format!("{:?} spans multiple lines", line)));
+    }
+    // Writing to a String never fails.
+    let _ = writeln!(out, "{}", line);
+    Ok(())
+}
+
+fn pdcstr_path(s: &PdCStr) -> PathBuf {
+    PathBuf::from(s.to_os_string())
+}
+
+fn pdcstr_text(s: &PdCStr) -> Result<String, ConfigError> {
+    os_text(&s.to_os_string())
+}
+
+fn os_text(s: &OsStr) -> Result<String, ConfigError> {
+    s.to_str()
+        .map(String::from)
+        .ok_or_else(|| ConfigError::Unrepresentable(format!("{:?} is not valid UTF-8", s)))
+}
+
+/// Paths under `root` are written relative to it, anything else is written as is.
+fn relative_path(root: &Path, path: &Path) -> Result<String, ConfigError> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if relative.as_os_str().is_empty() {
+        return Ok(String::from("."));
+    }
+    Ok(escape(&os_text(relative.as_os_str())?))
+}
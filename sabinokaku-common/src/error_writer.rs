@@ -0,0 +1,87 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+
+use netcorehost::dlopen::raw::Library;
+use netcorehost::pdcstring::{PdChar, PdCStr};
+
+type ErrorWriterFn = unsafe extern "C" fn(message: *const PdChar);
+/// Writers are nullable, unlike in the bindings of netcorehost, as hostfxr returns null if no writer was set.
+type SetErrorWriterFn = unsafe extern "C" fn(writer: Option<ErrorWriterFn>) -> Option<ErrorWriterFn>;
+
+thread_local! {
+    /// hostfxr keeps an error writer per thread, so messages are collected per thread as well.
+    static MESSAGES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// An error from booting the runtime, along with what hostfxr and hostpolicy wrote to their error writer
+/// while it happened, such as which framework could not be found.
+#[derive(Debug)]
+pub struct HostfxrError {
+    pub source: Box<dyn Error>,
+    pub messages: Vec<String>,
+}
+
+impl Display for HostfxrError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)?;
+        for message in &self.messages {
+            write!(f, "; hostfxr: {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for HostfxrError {
+    /// The source of the wrapped error, as the wrapped error itself is already part of the message.
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.source()
+    }
+}
+
+/// Collects what hostfxr writes to its error writer on the current thread, instead of it going to stderr,
+/// until dropped, when the previous writer is restored.
+pub(crate) struct ErrorCapture {
+    writer: Option<(Library, SetErrorWriterFn, Option<ErrorWriterFn>)>,
+}
+
+impl ErrorCapture {
+    /// Sets the error writer of the already loaded hostfxr at `path`. If it can not be set, nothing is collected.
+    pub fn install(path: &OsStr) -> ErrorCapture {
+        let writer = Library::open(path).ok().and_then(|library| {
+            let set_error_writer = unsafe { library.symbol::<SetErrorWriterFn>("hostfxr_set_error_writer") }.ok()?;
+            MESSAGES.with(|messages| messages.borrow_mut().clear());
+            let previous = unsafe { set_error_writer(Some(write_error)) };
+            Some((library, set_error_writer, previous))
+        });
+        ErrorCapture { writer }
+    }
+
+    /// Attaches the messages collected since the last error to `error`, if there are any.
+    pub fn attach(&self, error: impl Into<Box<dyn Error>>) -> Box<dyn Error> {
+        let messages = MESSAGES.with(|messages| std::mem::take(&mut *messages.borrow_mut()));
+        if messages.is_empty() {
+            error.into()
+        } else {
+            Box::new(HostfxrError { source: error.into(), messages })
+        }
+    }
+}
+
+impl Drop for ErrorCapture {
+    fn drop(&mut self) {
+        if let Some((_, set_error_writer, previous)) = self.writer.take() {
+            unsafe { set_error_writer(previous); }
+        }
+    }
+}
+
+unsafe extern "C" fn write_error(message: *const PdChar) {
+    if message.is_null() {
+        return;
+    }
+    let message = PdCStr::from_str_ptr(message).to_os_string();
+    let message = message.to_string_lossy();
+    MESSAGES.with(|messages| messages.borrow_mut().push(String::from(message.trim_end())));
+}
@@ -1,48 +1,204 @@
 use std::error::Error;
-use std::ffi::c_void;
-use netcorehost::hostfxr::Hostfxr;
+use std::ffi::{c_void, OsStr, OsString};
+use netcorehost::hostfxr::{AssemblyDelegateLoader, Hostfxr, HostfxrContext, MethodWithDefaultSignature, MethodWithUnknownSignature};
 
+use netcorehost::error::HostingResult;
 use netcorehost::nethost;
-use netcorehost::pdcstring::PdCString;
+use netcorehost::pdcstring::{PdCStr, PdCString};
 
-use crate::config::LoadConfig;
+use crate::boot::{BootContext, BootInfo};
+use crate::config::{EntryPoint, EntrySignature, EnvironmentSnapshot, LoadConfig};
+use crate::error_writer::ErrorCapture;
+use crate::report::{BootPhase, BootReport};
+pub use crate::error_writer::HostfxrError;
 
+pub mod boot;
 pub mod config;
+mod error_writer;
+pub mod report;
+pub mod services;
+
+/// The platform strings that configurations, the boot info and the services are made of.
+pub use netcorehost::pdcstring;
 
 pub mod prelude {
+    pub use crate::boot::*;
     pub use crate::config::*;
-    pub use crate::init_clr;
+    pub use crate::report::*;
+    pub use crate::services::*;
+    pub use crate::{init_clr, EntryResult, HostfxrError};
+}
+
+/// The outcome of calling a single managed entry point.
+#[derive(Debug)]
+pub struct EntryResult {
+    pub entry: EntryPoint,
+    /// The return code of the entry point, or why it could not be called.
+    pub result: Result<i32, Box<dyn Error>>,
+}
+
+impl EntryResult {
+    pub fn is_success(&self) -> bool {
+        matches!(self.result, Ok(0))
+    }
 }
 
-pub fn init_clr<T>(config: LoadConfig, args: Option<Vec<T>>) -> Result<i32, Box<dyn Error>> {
-    for (key, value) in config.environment_variables() {
-        std::env::set_var(key, value);
+/// Boots the runtime and calls every entry point of `config` in order, passing each the same
+/// [`SabinokakuBootInfo`](crate::boot::SabinokakuBootInfo) filled in from `boot`.
+///
+/// The boot info is freed once every entry point has returned if managed code freed it with
+/// [`free_args`](crate::boot::free_args), or if no entry point was called.
+///
+/// Calling stops at the first entry point that fails without `continue`. Errors booting the runtime itself
+/// are returned as an `Err`, otherwise every entry point that was called is reported, in the order it was called.
+///
+/// Variables under `restore-env` are restored once every entry point has been loaded, before any is called.
+///
+/// If `config` runs an application, its `Main` is run on the current thread instead, and only returns
+/// once `Main` does. `boot` and any `entry` directives are ignored, and the only result is that of `Main`.
+/// If hostfxr could not run `Main`, that result is an error rather than a return code.
+///
+/// Anything hostfxr writes to its error writer on the current thread is collected rather than written to stderr,
+/// and attached to the error it explains as a [`HostfxrError`].
+///
+/// How long each phase takes is recorded in `report`, along with where hostfxr was loaded from.
+pub fn init_clr(config: LoadConfig, boot: &BootContext,
+                report: &mut BootReport) -> Result<Vec<EntryResult>, Box<dyn Error>> {
+    let environment = config.apply_environment();
+
+    let (hostfxr_path, hostfxr) = report.time(BootPhase::HostfxrLoad, None, || -> Result<_, Box<dyn Error>> {
+        let hostfxr_path = match config.hostfxr_path() {
+            Some(hostfxr_path) => hostfxr_path.as_os_str().to_os_string(),
+            None => nethost::get_hostfxr_path()?,
+        };
+        let hostfxr = Hostfxr::load_from_path(&hostfxr_path)?;
+        Ok((hostfxr_path, hostfxr))
+    })?;
+    report.set_hostfxr(hostfxr_path.as_ref(), config.dotnetroot_path());
+    let errors = ErrorCapture::install(&hostfxr_path);
+
+    if config.is_application() {
+        return Ok(vec![run_app(&hostfxr, &config, environment, &errors, report)?]);
     }
 
-    let hostfxr = if let Some(hostfxr_path) = config.hostfxr_path() {
-        Hostfxr::load_from_path(hostfxr_path)?
-    } else {
-        nethost::load_hostfxr()?
+    let context = report.time(BootPhase::RuntimeInit, None, || -> Result<_, Box<dyn Error>> {
+        let context = if let Some(dotnet_path) = config.dotnetroot_path() {
+            hostfxr.initialize_for_runtime_config_with_dotnet_root(&config.runtime_config,
+                                                                   PdCString::from_os_str(dotnet_path.as_os_str())?)
+        } else {
+            hostfxr.initialize_for_runtime_config(&config.runtime_config)
+        }.map_err(|e| errors.attach(e))?;
+        set_runtime_properties(&context, &config).map_err(|e| errors.attach(e))?;
+        Ok(context)
+    })?;
+
+    let info = BootInfo::new(boot, argument_strings(&config)?)?;
+
+    let loaded: Vec<_> = config.entry_points().into_iter()
+        .map(|entry| {
+            let init = report.time(BootPhase::DelegateLoad, Some(&entry),
+                                   || context.get_delegate_loader_for_assembly(&entry.assembly))
+                .map_err(|e| -> Box<dyn Error> { Box::new(e) })
+                .and_then(|loader| report.time(BootPhase::EntryResolve, Some(&entry), || load_entry(&loader, &entry)))
+                .map_err(|e| errors.attach(e));
+            (entry, init)
+        })
+        .collect();
+    environment.restore();
+
+    let mut results = Vec::new();
+    let mut shared = false;
+    for (entry, init) in loaded {
+        let result = init.and_then(|init| {
+            // Managed code holds a reference from the first time the boot info is passed to it.
+            if !std::mem::replace(&mut shared, true) {
+                info.share();
+            }
+            report.time(BootPhase::ManagedCall, Some(&entry),
+                        || Ok(unsafe { init(info.as_ptr() as *const c_void, info.size()) }))
+        });
+        let result = EntryResult { entry, result };
+        let stop = !result.entry.continue_on_failure && !result.is_success();
+        results.push(result);
+        if stop {
+            break;
+        }
+    }
+    Ok(results)
+}
+
+/// Runs the application of a `kaku_x` configuration through hostfxr's command-line initialization.
+fn run_app(hostfxr: &Hostfxr, config: &LoadConfig, environment: EnvironmentSnapshot,
+           errors: &ErrorCapture, report: &mut BootReport) -> Result<EntryResult, Box<dyn Error>> {
+    // As on a command line, the application itself comes first.
+    let arguments = argument_strings(config)?;
+    let mut command_line: Vec<&PdCStr> = vec![&config.entry_assembly];
+    command_line.extend(arguments.iter().map(|arg| arg.as_ref()));
+
+    let context = report.time(BootPhase::RuntimeInit, None, || -> Result<_, Box<dyn Error>> {
+        let context = if let Some(dotnet_path) = config.dotnetroot_path() {
+            hostfxr.initialize_for_dotnet_command_line_with_args_and_dotnet_root(&command_line,
+                                                                                 PdCString::from_os_str(dotnet_path.as_os_str())?)
+        } else {
+            hostfxr.initialize_for_dotnet_command_line_with_args(&command_line)
+        }.map_err(|e| errors.attach(e))?;
+        set_runtime_properties(&context, config).map_err(|e| errors.attach(e))?;
+        Ok(context)
+    })?;
+    environment.restore();
+
+    let entry = EntryPoint {
+        assembly: config.entry_assembly.clone(),
+        type_name: config.type_name.clone(),
+        method: config.entry_method.clone(),
+        order: 0,
+        continue_on_failure: false,
+        signature: EntrySignature::Default,
     };
+    let result = report.time(BootPhase::ManagedCall, Some(&entry), || {
+        let code = context.run_app().value();
+        // The exit code of `Main`, unless hostfxr could not run it. Applications may exit with any code, so only
+        // codes that hostfxr defines are taken as its errors.
+        match HostingResult::from(code).into_result() {
+            Err(e) if e.is_known() => Err(errors.attach(Box::new(e))),
+            _ => Ok(code),
+        }
+    });
+    Ok(EntryResult { entry, result })
+}
 
-    let context = if let Some(dotnet_path) = config.dotnetroot_path() {
-        hostfxr.initialize_for_runtime_config_with_dotnet_root(&config.runtime_config,
-                                                               PdCString::from_os_str(dotnet_path.as_os_str())?)?
-    } else {
-        hostfxr.initialize_for_runtime_config(&config.runtime_config)?
+/// Resolves the function pointer of `entry` according to its signature.
+///
+/// Whatever the signature, the pointer is called as `int32_t (*)(void *args, size_t length)`, which is what
+/// the runtime marshals the default signature to.
+fn load_entry<'a, A: AsRef<PdCStr>>(loader: &'a AssemblyDelegateLoader<'a, A>,
+                                    entry: &EntryPoint) -> Result<MethodWithDefaultSignature, Box<dyn Error>> {
+    let method = match &entry.signature {
+        EntrySignature::Default => {
+            return Ok(loader.get_function_pointer_with_default_signature(&entry.type_name, &entry.method)?);
+        }
+        EntrySignature::UnmanagedCallersOnly => {
+            loader.get_function_pointer_for_unmanaged_callers_only_method(&entry.type_name, &entry.method)?
+        }
+        EntrySignature::Delegate(delegate) => loader.get_function_pointer(&entry.type_name, &entry.method, delegate)?,
     };
+    Ok(unsafe { std::mem::transmute::<MethodWithUnknownSignature, MethodWithDefaultSignature>(method) })
+}
 
-    let loader = context.get_delegate_loader_for_assembly(&config.entry_assembly)?;
-    let init = loader.get_function_pointer_with_default_signature(config.type_name, config.entry_method)?;
+fn set_runtime_properties<I>(context: &HostfxrContext<I>, config: &LoadConfig) -> Result<(), Box<dyn Error>> {
+    for (name, value) in config.runtime_properties() {
+        context.set_runtime_property_value(PdCString::from_os_str(name)?, PdCString::from_os_str(value)?)?;
+    }
+    Ok(())
+}
 
-    if let Some(mut args) = args {
-        args.shrink_to_fit();
-        let len = args.len();
-        let refs = args.leak();
-        Ok(unsafe {
-            init(refs.as_mut_ptr() as *const c_void, len)
-        })
-    } else {
-        Ok(unsafe { init(std::ptr::null(), 0) })
+/// The `arg` directives of `config`, followed by the arguments of the host process if they are forwarded.
+fn argument_strings(config: &LoadConfig) -> Result<Vec<PdCString>, Box<dyn Error>> {
+    let mut arguments: Vec<OsString> = config.arguments().map(OsStr::to_os_string).collect();
+    if config.forwards_arguments() {
+        arguments.extend(std::env::args_os().skip(1));
     }
-}
\ No newline at end of file
+    Ok(arguments.into_iter()
+        .map(PdCString::from_os_str)
+        .collect::<Result<Vec<_>, _>>()?)
+}
@@ -0,0 +1,277 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::boot::BootTrigger;
+use crate::config::EntryPoint;
+use crate::EntryResult;
+
+/// The version of the JSON written by [`BootReport::to_json`].
+pub const BOOT_REPORT_VERSION: u32 = 1;
+
+/// A step of booting the runtime that is timed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootPhase {
+    ConfigSearch,
+    ConfigParse,
+    HostfxrLoad,
+    /// Initializing hostfxr for the runtime configuration, and setting runtime properties.
+    RuntimeInit,
+    /// Getting the delegate loader for the assembly of an entry point, which starts the runtime the first time.
+    DelegateLoad,
+    /// Loading the assembly of an entry point if it is not loaded yet, and resolving its function pointer, which
+    /// hostfxr does in a single call.
+    EntryResolve,
+    /// Calling an entry point, or running the `Main` of an application.
+    ManagedCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseTiming {
+    pub phase: BootPhase,
+    /// The entry point the phase is for, if it is specific to one.
+    pub entry: Option<String>,
+    pub duration_ms: f64,
+    pub ok: bool,
+}
+
+/// The outcome of calling an entry point, with either its return code or the chain of errors why it could not be called.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntryOutcome {
+    pub entry: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Vec<String>>,
+}
+
+/// What happened while booting the runtime, and how long each phase took, written by the `report` directive.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BootReport {
+    pub version: u32,
+    pub pid: u32,
+    pub trigger: Option<BootTrigger>,
+    pub config_path: Option<PathBuf>,
+    /// The configuration that was booted with its section selected, in the long format.
+    pub config: Option<String>,
+    pub hostfxr_path: Option<PathBuf>,
+    /// The `dotnetroot` directive, or the root hostfxr was found in.
+    pub dotnet_root: Option<PathBuf>,
+    pub phases: Vec<PhaseTiming>,
+    pub entries: Vec<EntryOutcome>,
+    /// The chain of errors if the runtime could not be booted.
+    pub error: Option<Vec<String>>,
+    /// The code of the first entry point to fail, or `0`.
+    pub return_code: i32,
+}
+
+impl BootReport {
+    pub fn new() -> BootReport {
+        BootReport {
+            version: BOOT_REPORT_VERSION,
+            pid: std::process::id(),
+            trigger: None,
+            config_path: None,
+            config: None,
+            hostfxr_path: None,
+            dotnet_root: None,
+            phases: Vec::new(),
+            entries: Vec::new(),
+            error: None,
+            return_code: 0,
+        }
+    }
+
+    /// Runs `f` as `phase`, recording how long it took and whether it succeeded.
+    pub fn time<T, E>(&mut self, phase: BootPhase, entry: Option<&EntryPoint>, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let start = Instant::now();
+        let result = f();
+        self.phases.push(PhaseTiming {
+            phase,
+            entry: entry.map(ToString::to_string),
+            duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+            ok: result.is_ok(),
+        });
+        result
+    }
+
+    /// Records where hostfxr was loaded from, and the root of the installation it belongs to if `dotnet_root` is not given.
+    pub fn set_hostfxr(&mut self, hostfxr_path: &Path, dotnet_root: Option<&Path>) {
+        self.hostfxr_path = Some(hostfxr_path.to_path_buf());
+        // hostfxr is installed as `DOTNET_ROOT/host/fxr/VERSION/hostfxr`.
+        let installed_root = hostfxr_path.ancestors().nth(4)
+            .filter(|root| hostfxr_path.starts_with(root.join("host").join("fxr")));
+        self.dotnet_root = dotnet_root.or(installed_root).map(Path::to_path_buf);
+    }
+
+    /// Records the outcome of booting, as returned by [`init_clr`](crate::init_clr).
+    pub fn set_results(&mut self, results: &Result<Vec<EntryResult>, Box<dyn Error>>) {
+        match results {
+            Ok(results) => {
+                self.entries = results.iter()
+                    .map(|result| EntryOutcome {
+                        entry: result.entry.to_string(),
+                        return_code: result.result.as_ref().ok().copied(),
+                        error: result.result.as_ref().err().map(|e| error_chain(e.as_ref())),
+                    })
+                    .collect();
+                // The first entry point to fail decides the result of the boot as a whole.
+                self.return_code = results.iter()
+                    .find_map(|result| match result.result {
+                        Ok(0) => None,
+                        Ok(code) => Some(code),
+                        Err(_) => Some(1),
+                    })
+                    .unwrap_or(0);
+            }
+            Err(e) => {
+                self.error = Some(error_chain(e.as_ref()));
+                self.return_code = 1;
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        // Every field serializes, so this can not fail.
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+impl Default for BootReport {
+    fn default() -> Self {
+        BootReport::new()
+    }
+}
+
+/// The message of `error`, followed by those of its sources.
+fn error_chain(error: &dyn Error) -> Vec<String> {
+    let mut chain = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(e) = source {
+        chain.push(e.to_string());
+        source = e.source();
+    }
+    chain
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::fmt::{Display, Formatter};
+    use std::path::PathBuf;
+
+    use crate::boot::BootTrigger;
+    use crate::config::LoadConfig;
+    use crate::report::{error_chain, BootPhase, BootReport};
+    use crate::{EntryResult, HostfxrError};
+
+    #[derive(Debug)]
+    struct Outer(std::io::Error);
+
+    impl Display for Outer {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            write!(f, "could not load the assembly")
+        }
+    }
+
+    impl Error for Outer {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn outer() -> Box<dyn Error> {
+        Box::new(Outer(std::io::Error::new(std::io::ErrorKind::NotFound, "no such file")))
+    }
+
+    #[test]
+    fn test_error_chain() {
+        assert_eq!(error_chain(outer().as_ref()), vec!["could not load the assembly", "no such file"]);
+
+        // The wrapped error is part of the message already, so only its sources follow.
+        let error = HostfxrError { source: outer(), messages: vec![String::from("framework not found")] };
+        assert_eq!(error_chain(&error), vec!["could not load the assembly; hostfxr: framework not found", "no such file"]);
+    }
+
+    #[test]
+    fn test_phase_order() {
+        let config = "kaku_s\nFirst::First.Plugin!Init\nentry Second::Second.Plugin!Init";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &config).unwrap();
+        let entries = config.entry_points();
+
+        let mut report = BootReport::new();
+        report.time(BootPhase::RuntimeInit, None, || Ok::<_, ()>(())).unwrap();
+        for entry in &entries {
+            report.time(BootPhase::DelegateLoad, Some(entry), || Ok::<_, ()>(())).unwrap();
+        }
+        assert!(report.time(BootPhase::EntryResolve, Some(&entries[1]), || Err::<(), _>(())).is_err());
+
+        let phases: Vec<_> = report.phases.iter().map(|p| (p.phase, p.entry.as_deref(), p.ok)).collect();
+        assert_eq!(phases, vec![
+            (BootPhase::RuntimeInit, None, true),
+            (BootPhase::DelegateLoad, Some("First.Plugin, First!Init"), true),
+            (BootPhase::DelegateLoad, Some("Second.Plugin, Second!Init"), true),
+            (BootPhase::EntryResolve, Some("Second.Plugin, Second!Init"), false),
+        ]);
+    }
+
+    #[test]
+    fn test_set_results() {
+        let config = "kaku_s\nFirst::First.Plugin!Init\nentry Second::Second.Plugin!Init\nentry Third::Third.Plugin!Init";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &config).unwrap();
+        let mut entries = config.entry_points().into_iter();
+        let results = vec![
+            EntryResult { entry: entries.next().unwrap(), result: Ok(0) },
+            EntryResult { entry: entries.next().unwrap(), result: Err(outer()) },
+            EntryResult { entry: entries.next().unwrap(), result: Ok(3) },
+        ];
+
+        let mut report = BootReport::new();
+        report.set_results(&Ok(results));
+        assert_eq!(report.return_code, 1);
+        assert_eq!(report.entries.iter().map(|e| (e.entry.as_str(), e.return_code)).collect::<Vec<_>>(), vec![
+            ("First.Plugin, First!Init", Some(0)),
+            ("Second.Plugin, Second!Init", None),
+            ("Third.Plugin, Third!Init", Some(3)),
+        ]);
+        assert_eq!(report.entries[1].error, Some(vec![String::from("could not load the assembly"), String::from("no such file")]));
+        assert_eq!(report.error, None);
+
+        let mut report = BootReport::new();
+        report.set_results(&Err(outer()));
+        assert_eq!(report.return_code, 1);
+        assert!(report.entries.is_empty());
+        assert_eq!(report.error, Some(vec![String::from("could not load the assembly"), String::from("no such file")]));
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let config = "kaku_s\nFirst::First.Plugin!Init";
+        let config = LoadConfig::try_parse(PathBuf::from("/app/kaku.co"), &config).unwrap();
+        let entry = config.entry_points().remove(0);
+
+        let mut report = BootReport::new();
+        report.trigger = Some(BootTrigger::Api);
+        report.time(BootPhase::DelegateLoad, Some(&entry), || Ok::<_, ()>(())).unwrap();
+        report.set_results(&Ok(vec![EntryResult { entry, result: Ok(0) }]));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        let mut keys: Vec<_> = json.as_object().unwrap().keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(keys, vec!["config", "config_path", "dotnet_root", "entries", "error", "hostfxr_path", "phases",
+                              "pid", "return_code", "trigger", "version"]);
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["trigger"], "api");
+        assert_eq!(json["phases"][0]["phase"], "delegate_load");
+        assert_eq!(json["phases"][0]["entry"], "First.Plugin, First!Init");
+        assert_eq!(json["phases"][0]["ok"], true);
+        assert!(json["phases"][0]["duration_ms"].is_f64());
+        // Only one of `return_code` and `error` is written for an entry point.
+        assert_eq!(json["entries"][0], serde_json::json!({ "entry": "First.Plugin, First!Init", "return_code": 0 }));
+        assert_eq!(json["error"], serde_json::Value::Null);
+    }
+}
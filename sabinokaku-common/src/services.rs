@@ -0,0 +1,106 @@
+use std::ffi::c_void;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use netcorehost::pdcstring::PdChar;
+
+/// The version of the services table provided by this version of sabinokaku.
+pub const SERVICES_VERSION: u32 = 1;
+
+/// The severity of a message logged through the `log` service.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/// Called once when the host process exits, with the context it was registered with.
+pub type ShutdownCallback = unsafe extern "C" fn(context: *mut c_void);
+
+/// Functions through which managed code calls back into sabinokaku, pointed to by the boot info.
+///
+/// Like the boot info, fields are only ever added at the end along with an increase of `version`. Strings are
+/// null-terminated platform strings, and strings returned by sabinokaku stay valid for the lifetime of the process.
+/// Every function may be called from any thread, at any time after the boot info is passed to an entry point.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SabinokakuServices {
+    /// The version of the services table when it was filled in.
+    pub version: u32,
+    /// The size of the table in bytes.
+    pub size: u32,
+    /// Logs `message` through sabinokaku at a log level. Unknown levels are logged at the info level.
+    pub log: unsafe extern "C" fn(level: u32, message: *const PdChar),
+    /// The configuration that was booted with its section selected, written in the long format with paths
+    /// relative to `config_path` of the boot info, or null if it can not be written.
+    pub config: extern "C" fn() -> *const PdChar,
+    /// The value of the `index`th custom `x-NAME` directive named `name`, without the `x-`, or null if there is none.
+    pub custom_directive: unsafe extern "C" fn(name: *const PdChar, index: usize) -> *const PdChar,
+    /// The boot status of the process, the same as `sabinokaku_status`.
+    pub status: extern "C" fn() -> i32,
+    /// Registers `callback` to be called with `context` when the host process exits, in the reverse order of
+    /// registration. Returns `1` if it was registered, or `0` if the process is already shutting down.
+    ///
+    /// On Linux, callbacks run from an `atexit` hook registered while booting, so after any registered later and
+    /// before the host waits for the boot thread with `on-exit wait`, which lets them tell managed code to finish.
+    /// On Windows, they are best-effort: they run under the loader lock when `kaku.dll` is unloaded with
+    /// `FreeLibrary`, and not at all when the process terminates.
+    pub on_shutdown: unsafe extern "C" fn(callback: ShutdownCallback, context: *mut c_void) -> i32,
+    /// Signals that managed code is ready for the host process to carry on in the `boot ready` mode. Does nothing in
+    /// any other mode.
+    pub ready: extern "C" fn(),
+}
+
+impl LogLevel {
+    /// The level of `level`, where unknown levels are [`LogLevel::Info`].
+    pub fn from_u32(level: u32) -> LogLevel {
+        match level {
+            1 => LogLevel::Error,
+            2 => LogLevel::Warn,
+            4 => LogLevel::Debug,
+            5 => LogLevel::Trace,
+            _ => LogLevel::Info,
+        }
+    }
+}
+
+impl LogLevel {
+    /// Parses a level, or `off` as `Some(None)` to log nothing. Returns `None` if `s` is neither.
+    pub fn parse_filter(s: &str) -> Option<Option<LogLevel>> {
+        match s {
+            "off" => Some(None),
+            _ => s.parse().ok().map(Some),
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(())
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        })
+    }
+}
@@ -0,0 +1,266 @@
+#ifndef SABINOKAKU_H
+#define SABINOKAKU_H
+
+/* Generated by cbindgen, do not edit. Run `cbindgen --config cbindgen.toml --output include/sabinokaku.h` in sabinokaku-loader, which `cargo test` checks. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#if defined(_WIN32)
+#include <wchar.h>
+/* UTF-16 on Windows. */
+typedef wchar_t sabinokaku_char_t;
+#else
+/* UTF-8 elsewhere. */
+typedef char sabinokaku_char_t;
+#endif
+
+/*
+ The configuration could not be read or parsed, or a pointer passed in was null or not UTF-8.
+ */
+#define SABINOKAKU_INVALID_CONFIG -1
+
+/*
+ The runtime is booting or was booted in this process already, and can not be booted again.
+ */
+#define SABINOKAKU_ALREADY_BOOTED -2
+
+/*
+ The version of the boot info filled in by this version of sabinokaku.
+ */
+#define SABINOKAKU_BOOT_INFO_VERSION 1
+
+/*
+ The version of the services table provided by this version of sabinokaku.
+ */
+#define SABINOKAKU_SERVICES_VERSION 1
+
+/*
+ How far booting the runtime in this process has come. The runtime can only be booted once per process.
+ */
+enum SabinokakuBootStatus
+#ifdef __cplusplus
+  : int32_t
+#endif // __cplusplus
+ {
+  SABINOKAKU_BOOT_STATUS_NOT_BOOTED = 0,
+  SABINOKAKU_BOOT_STATUS_BOOTING = 1,
+  /*
+   Booted, and every entry point that was called returned zero.
+   */
+  SABINOKAKU_BOOT_STATUS_BOOTED = 2,
+  /*
+   Booting failed, or an entry point failed or returned non-zero.
+   */
+  SABINOKAKU_BOOT_STATUS_FAILED = 3,
+};
+#ifndef __cplusplus
+typedef int32_t SabinokakuBootStatus;
+#endif // __cplusplus
+
+/*
+ What booted the runtime.
+ */
+enum SabinokakuBootTrigger
+#ifdef __cplusplus
+  : uint32_t
+#endif // __cplusplus
+ {
+  /*
+   `libkaku.so` was preloaded, and booted before the `main` of the host process.
+   */
+  SABINOKAKU_BOOT_TRIGGER_PRELOAD = 1,
+  /*
+   `kaku.dll` was loaded, and booted from `DllMain`.
+   */
+  SABINOKAKU_BOOT_TRIGGER_DLL_MAIN = 2,
+  /*
+   The Vulkan layer booted after the first `vkCreateInstance`.
+   */
+  SABINOKAKU_BOOT_TRIGGER_VK_CREATE_INSTANCE = 3,
+  /*
+   The Vulkan layer booted after the first `vkCreateDevice`.
+   */
+  SABINOKAKU_BOOT_TRIGGER_VK_CREATE_DEVICE = 4,
+  /*
+   A host booted deliberately through the C API.
+   */
+  SABINOKAKU_BOOT_TRIGGER_API = 5,
+};
+#ifndef __cplusplus
+typedef uint32_t SabinokakuBootTrigger;
+#endif // __cplusplus
+
+/*
+ The severity of a message logged through the `log` service.
+ */
+enum SabinokakuLogLevel
+#ifdef __cplusplus
+  : uint32_t
+#endif // __cplusplus
+ {
+  SABINOKAKU_LOG_LEVEL_ERROR = 1,
+  SABINOKAKU_LOG_LEVEL_WARN = 2,
+  SABINOKAKU_LOG_LEVEL_INFO = 3,
+  SABINOKAKU_LOG_LEVEL_DEBUG = 4,
+  SABINOKAKU_LOG_LEVEL_TRACE = 5,
+};
+#ifndef __cplusplus
+typedef uint32_t SabinokakuLogLevel;
+#endif // __cplusplus
+
+/*
+ Called once when the host process exits, with the context it was registered with.
+ */
+typedef void (*SabinokakuShutdownCallback)(void *context);
+
+/*
+ Functions through which managed code calls back into sabinokaku, pointed to by the boot info.
+
+ Like the boot info, fields are only ever added at the end along with an increase of `version`. Strings are
+ null-terminated platform strings, and strings returned by sabinokaku stay valid for the lifetime of the process.
+ Every function may be called from any thread, at any time after the boot info is passed to an entry point.
+ */
+typedef struct SabinokakuServices {
+  /*
+   The version of the services table when it was filled in.
+   */
+  uint32_t version;
+  /*
+   The size of the table in bytes.
+   */
+  uint32_t size;
+  /*
+   Logs `message` through sabinokaku at a log level. Unknown levels are logged at the info level.
+   */
+  void (*log)(uint32_t level, const sabinokaku_char_t *message);
+  /*
+   The configuration that was booted with its section selected, written in the long format with paths
+   relative to `config_path` of the boot info, or null if it can not be written.
+   */
+  const sabinokaku_char_t *(*config)(void);
+  /*
+   The value of the `index`th custom `x-NAME` directive named `name`, without the `x-`, or null if there is none.
+   */
+  const sabinokaku_char_t *(*custom_directive)(const sabinokaku_char_t *name, uintptr_t index);
+  /*
+   The boot status of the process, the same as `sabinokaku_status`.
+   */
+  int32_t (*status)(void);
+  /*
+   Registers `callback` to be called with `context` when the host process exits, in the reverse order of
+   registration. Returns `1` if it was registered, or `0` if the process is already shutting down.
+
+   On Linux, callbacks run from an `atexit` hook registered while booting, so after any registered later and
+   before the host waits for the boot thread with `on-exit wait`, which lets them tell managed code to finish.
+   On Windows, they are best-effort: they run under the loader lock when `kaku.dll` is unloaded with
+   `FreeLibrary`, and not at all when the process terminates.
+   */
+  int32_t (*on_shutdown)(SabinokakuShutdownCallback callback, void *context);
+  /*
+   Signals that managed code is ready for the host process to carry on in the `boot ready` mode. Does nothing in
+   any other mode.
+   */
+  void (*ready)(void);
+} SabinokakuServices;
+
+/*
+ Passed to every managed entry point as `args`, with `sizeBytes` set to its size.
+
+ Fields are only ever added at the end, along with an increase of `version`, so a payload built against
+ an older version can read any newer one. Strings are null-terminated platform strings, UTF-8 on Linux and
+ UTF-16 on Windows, and are null if unknown.
+
+ Everything the struct points to stays valid until managed code frees it with `free_args` and every entry point
+ has returned, or for the lifetime of the process if it is never freed.
+ */
+typedef struct SabinokakuBootInfo {
+  /*
+   The version of the boot info when the struct was filled in.
+   */
+  uint32_t version;
+  /*
+   The size of the struct in bytes, as filled in.
+   */
+  uint32_t size;
+  SabinokakuBootTrigger trigger;
+  uint32_t host_pid;
+  const sabinokaku_char_t *config_path;
+  const sabinokaku_char_t *module_path;
+  /*
+   The arguments of the host process, including the executable.
+   */
+  uintptr_t host_argc;
+  const sabinokaku_char_t *const *host_argv;
+  /*
+   The strings of `arg` directives, followed by those of the host process if they are forwarded.
+   */
+  uintptr_t argc;
+  const sabinokaku_char_t *const *argv;
+  /*
+   Specific to the trigger, such as the `VkInstance` and `VkDevice` handles for Vulkan triggers, or null.
+   */
+  const void *trigger_data;
+  uintptr_t trigger_data_size;
+  /*
+   Frees the boot info, the same as the exported `sabinokaku_free_args`.
+   */
+  int32_t (*free_args)(const struct SabinokakuBootInfo*);
+  /*
+   The services of sabinokaku, which stay valid for the lifetime of the process, or null.
+   */
+  const struct SabinokakuServices *services;
+} SabinokakuBootInfo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Frees the boot info passed to the entry points once they have all returned, the same as its `free_args`. Returns
+ `1` if it was released, or `0` if `info` is null, was already freed, or is not a boot info, which is safe to do.
+
+ # Safety
+ `info` may be any pointer, but only pointers passed to an entry point are ever freed.
+ */
+int32_t sabinokaku_free_args(const struct SabinokakuBootInfo *info);
+
+/*
+ Signals that managed code is ready, releasing a `main` waiting in the `boot ready` mode.
+ */
+void sabinokaku_ready(void);
+
+/*
+ Loads the configuration file at `path` and boots the runtime on the calling thread, returning once every
+ entry point has returned.
+
+ Returns `0` if every entry point returned zero, the code of the first entry point to fail, or one of the
+ negative `SABINOKAKU_*` codes. `on-failure` and `on-return` policies still apply, and may end the process.
+
+ # Safety
+ `path` must be null or point to a null-terminated string.
+ */
+int32_t sabinokaku_boot_from_path(const char *path);
+
+/*
+ Parses `config` as the contents of a configuration file and boots the runtime the same as
+ `sabinokaku_boot_from_path`. Relative paths and `${ORIGIN}` resolve against the directory `origin`, or
+ the current directory if it is null, as if the configuration were the file `kaku.co` in it. `include` directives
+ are merged, but drop-ins in `kaku.d` are not.
+
+ # Safety
+ `config` must be null or point to a null-terminated string, and so must `origin`.
+ */
+int32_t sabinokaku_boot_from_string(const char *config,
+                                    const char *origin);
+
+/*
+ The boot status of this process, from any thread and at any time.
+ */
+int32_t sabinokaku_status(void);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* SABINOKAKU_H */
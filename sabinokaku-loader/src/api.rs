@@ -0,0 +1,91 @@
+//! The C API, for native hosts that load `kaku` themselves and boot the runtime deliberately.
+//!
+//! Declared in `include/sabinokaku.h`. Paths and configuration strings are null-terminated UTF-8 on every platform.
+
+use std::error::Error;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+use sabinokaku_common::prelude::*;
+
+use crate::OsConfigSearchPath;
+
+/// The configuration could not be read or parsed, or a pointer passed in was null or not UTF-8.
+pub const SABINOKAKU_INVALID_CONFIG: i32 = -1;
+/// The runtime is booting or was booted in this process already, and can not be booted again.
+pub const SABINOKAKU_ALREADY_BOOTED: i32 = -2;
+
+/// Loads the configuration file at `path` and boots the runtime on the calling thread, returning once every
+/// entry point has returned.
+///
+/// Returns `0` if every entry point returned zero, the code of the first entry point to fail, or one of the
+/// negative `SABINOKAKU_*` codes. `on-failure` and `on-return` policies still apply, and may end the process.
+///
+/// # Safety
+/// `path` must be null or point to a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sabinokaku_boot_from_path(path: *const c_char) -> i32 {
+    let path = match c_str(path) {
+        Some(path) => PathBuf::from(path),
+        None => return SABINOKAKU_INVALID_CONFIG,
+    };
+    let mut report = BootReport::new();
+    let config = report.time(BootPhase::ConfigParse, None,
+                             || LoadConfig::load(&path, &OsConfigSearchPath::expansion_context()));
+    boot_loaded(config, path.canonicalize().unwrap_or(path), report.phases)
+}
+
+/// Parses `config` as the contents of a configuration file and boots the runtime the same as
+/// `sabinokaku_boot_from_path`. Relative paths and `${ORIGIN}` resolve against the directory `origin`, or
+/// the current directory if it is null, as if the configuration were the file `kaku.co` in it. `include` directives
+/// are merged, but drop-ins in `kaku.d` are not.
+///
+/// # Safety
+/// `config` must be null or point to a null-terminated string, and so must `origin`.
+#[no_mangle]
+pub unsafe extern "C" fn sabinokaku_boot_from_string(config: *const c_char, origin: *const c_char) -> i32 {
+    let input = match c_str(config) {
+        Some(input) => input,
+        None => return SABINOKAKU_INVALID_CONFIG,
+    };
+    let origin = if origin.is_null() {
+        std::env::current_dir().unwrap_or_default()
+    } else {
+        match c_str(origin) {
+            Some(origin) => PathBuf::from(origin),
+            None => return SABINOKAKU_INVALID_CONFIG,
+        }
+    };
+    // The file does not exist, but it decides the directory that relative paths resolve against.
+    let cfg_path = origin.join("kaku.co");
+    let mut report = BootReport::new();
+    let config = report.time(BootPhase::ConfigParse, None,
+                             || LoadConfig::load_str(&cfg_path, input, &OsConfigSearchPath::expansion_context()));
+    boot_loaded(config, cfg_path, report.phases)
+}
+
+/// The boot status of this process, from any thread and at any time.
+#[no_mangle]
+pub extern "C" fn sabinokaku_status() -> i32 {
+    crate::STATUS.load(Ordering::Acquire)
+}
+
+fn boot_loaded(config: Result<LoadConfig, Box<dyn Error>>, cfg_path: PathBuf, phases: Vec<PhaseTiming>) -> i32 {
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Error occurred when parsing config: {}", e);
+            return SABINOKAKU_INVALID_CONFIG;
+        }
+    };
+    crate::boot_clr(crate::prepare_config(config, cfg_path, phases), BootTrigger::Api, Vec::new())
+}
+
+unsafe fn c_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    CStr::from_ptr(ptr).to_str().ok()
+}
@@ -2,6 +2,9 @@
 #![feature(once_cell)]
 #![feature(ptr_const_cast)]
 
+#[macro_use]
+mod log;
+
 #[cfg(all(target_os = "windows"))]
 mod windows;
 
@@ -11,8 +14,16 @@ mod linux;
 #[cfg(feature = "vulkan")]
 mod vulkan;
 
+mod api;
+mod services;
+
 use std::error::Error;
-use std::io::Read;
+use std::lazy::SyncOnceCell;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
 use sabinokaku_common::prelude::*;
 
 #[cfg(all(target_os = "windows"))]
@@ -21,15 +32,196 @@ use crate::windows::WindowsConfigSearchPath as OsConfigSearchPath;
 #[cfg(all(target_os = "linux"))]
 use crate::linux::LinuxConfigSearchPath as OsConfigSearchPath;
 
-fn get_config() -> Result<LoadConfig, Box<dyn Error>> {
-    let cfg_path = OsConfigSearchPath::search_for_config()?;
-    let mut file = std::fs::File::open(&cfg_path)?;
-    let mut cfg_string = String::new();
-    file.read_to_string(&mut cfg_string)?;
-    LoadConfig::try_parse(cfg_path, &cfg_string)
+/// The configuration file that was loaded, to pass on to the entry points.
+static CONFIG_PATH: SyncOnceCell<PathBuf> = SyncOnceCell::new();
+
+/// How long searching for and parsing the configuration took, for the boot report.
+static CONFIG_PHASES: SyncOnceCell<Vec<PhaseTiming>> = SyncOnceCell::new();
+
+/// The [`BootStatus`] of this process.
+static STATUS: AtomicI32 = AtomicI32::new(BootStatus::NotBooted as i32);
+
+/// Set once managed code signalled it is ready, or booting finished, for [`BootMode::Ready`].
+static READY: SyncOnceCell<Signal> = SyncOnceCell::new();
+
+/// A flag that is set once, and that other threads can wait on.
+#[derive(Default)]
+struct Signal {
+    set: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl Signal {
+    fn set(&self) {
+        *self.set.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.changed.notify_all();
+    }
+
+    /// Waits until the signal is set, returning `false` if it was not within `timeout`.
+    #[cfg(target_os = "linux")]
+    fn wait(&self, timeout: Duration) -> bool {
+        let set = self.set.lock().unwrap_or_else(PoisonError::into_inner);
+        match self.changed.wait_timeout_while(set, timeout, |set| !*set) {
+            Ok((_, result)) => !result.timed_out(),
+            Err(e) => !e.into_inner().1.timed_out(),
+        }
+    }
+}
+
+/// Finds and loads the configuration, or `None` if a registry maps nothing to this process.
+#[cfg(any(target_os = "windows", feature = "vulkan"))]
+fn get_config() -> Result<Option<LoadConfig>, Box<dyn Error>> {
+    let mut report = BootReport::new();
+    match find_config(&mut report)? {
+        Some(cfg_path) => load_config(cfg_path, report).map(Some),
+        None => Ok(None)
+    }
+}
+
+/// Finds the configuration file, or `None` if a registry maps nothing to this process. The search is timed
+/// in `report`.
+fn find_config(report: &mut BootReport) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    match report.time(BootPhase::ConfigSearch, None, OsConfigSearchPath::search_for_config) {
+        Ok(cfg_path) => Ok(Some(cfg_path)),
+        Err(e) => match e.downcast_ref::<ConfigError>() {
+            Some(ConfigError::Unmapped(tried)) => {
+                debug!("No configuration is mapped to this executable, and none was found at {:?}.", tried);
+                Ok(None)
+            }
+            _ => Err(e)
+        }
+    }
+}
+
+/// Loads the configuration file found by [`find_config`], whose search was timed in `report`.
+fn load_config(cfg_path: PathBuf, mut report: BootReport) -> Result<LoadConfig, Box<dyn Error>> {
+    let config = report.time(BootPhase::ConfigParse, None,
+                             || LoadConfig::load(&cfg_path, &OsConfigSearchPath::expansion_context()))?;
+    Ok(prepare_config(config, cfg_path, report.phases))
+}
+
+/// Configures logging and reports the warnings of a configuration loaded from `cfg_path`, and selects its
+/// section for this process. `phases` are the timings of finding and loading it.
+fn prepare_config(mut config: LoadConfig, cfg_path: PathBuf, phases: Vec<PhaseTiming>) -> LoadConfig {
+    log::configure(&config);
+    info!("Loaded configuration {}.", cfg_path.display());
+    CONFIG_PATH.get_or_init(|| cfg_path);
+    CONFIG_PHASES.get_or_init(|| phases);
+    for warning in &config.warnings {
+        warn!("{}", warning);
+    }
+    if config.has_sections() {
+        match config.select_section(&OsConfigSearchPath::host_context()) {
+            Some(section) => info!("Using section `{}`.", section),
+            None => info!("No section matched this process."),
+        }
+    }
+    config
+}
+
+/// Frees the boot info passed to the entry points once they have all returned, the same as its `free_args`. Returns
+/// `1` if it was released, or `0` if `info` is null, was already freed, or is not a boot info, which is safe to do.
+///
+/// # Safety
+/// `info` may be any pointer, but only pointers passed to an entry point are ever freed.
+#[no_mangle]
+pub unsafe extern "C" fn sabinokaku_free_args(info: *const SabinokakuBootInfo) -> i32 {
+    free_args(info)
+}
+
+/// Signals that managed code is ready, releasing a `main` waiting in the `boot ready` mode.
+#[no_mangle]
+pub extern "C" fn sabinokaku_ready() {
+    READY.get_or_init(Signal::default).set();
+}
+
+/// Boots the runtime from `trigger`, where `trigger_data` is specific to the trigger.
+///
+/// Failures and non-zero entry points are handled by the `on-failure` and `on-return` policies of `config`,
+/// which may end the host process. Otherwise, the code of the first to fail is returned, or
+/// [`api::SABINOKAKU_ALREADY_BOOTED`] if the runtime was already booted in this process.
+///
+/// The boot report is written before any policy is applied, so that it is written even if the process ends.
+fn boot_clr(config: LoadConfig, trigger: BootTrigger, trigger_data: Vec<u64>) -> i32 {
+    if config.skip_boot() {
+        info!("Section asked to skip this process, not booting CLR.");
+        return 0;
+    }
+    if STATUS.compare_exchange(BootStatus::NotBooted as i32, BootStatus::Booting as i32,
+                               Ordering::AcqRel, Ordering::Acquire).is_err() {
+        warn!("CLR was already booted in this process.");
+        return api::SABINOKAKU_ALREADY_BOOTED;
+    }
+    let code = run_entry_points(config, trigger, trigger_data);
+    let status = if code == 0 { BootStatus::Booted } else { BootStatus::Failed };
+    STATUS.store(status as i32, Ordering::Release);
+    code
+}
+
+fn run_entry_points(config: LoadConfig, trigger: BootTrigger, trigger_data: Vec<u64>) -> i32 {
+    debug!("Booting CLR from {:?}.", trigger);
+    let (on_failure, on_return) = (config.on_failure(), config.on_return());
+    let cfg_path = CONFIG_PATH.get().map(PathBuf::as_path);
+    services::snapshot_config(&config, cfg_path);
+    services::register_shutdown();
+    let boot = BootContext {
+        config_path: CONFIG_PATH.get().cloned(),
+        module_path: OsConfigSearchPath::get_module_path(),
+        trigger_data,
+        services: Some(&services::SERVICES),
+        ..BootContext::new(trigger)
+    };
+    let report_path = config.report_path().map(Path::to_path_buf);
+    let mut report = BootReport {
+        trigger: Some(trigger),
+        config_path: boot.config_path.clone(),
+        config: cfg_path.and_then(|path| config.write(path, ConfigFormat::Long).ok()),
+        phases: CONFIG_PHASES.get().cloned().unwrap_or_default(),
+        ..BootReport::new()
+    };
+    let results = sabinokaku_common::init_clr(config, &boot, &mut report);
+    report.set_results(&results);
+    if let Some(report_path) = report_path {
+        write_report(&report, &report_path);
+    }
+
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            apply_policy(on_failure, 1, format_args!("Error occurred when injecting CLR: {}", e));
+            return 1;
+        }
+    };
+    for result in &results {
+        match &result.result {
+            Ok(0) => info!("Entry point {} returned 0.", result.entry),
+            Ok(code) => apply_policy(on_return, *code, format_args!("Entry point {} returned {}", result.entry, code)),
+            Err(e) => apply_policy(on_failure, 1, format_args!("Entry point {} could not be called: {}", result.entry, e)),
+        }
+    }
+    report.return_code
+}
+
+fn write_report(report: &BootReport, path: &Path) {
+    match std::fs::write(path, report.to_json()) {
+        Ok(()) => info!("Wrote boot report {}.", path.display()),
+        Err(e) => warn!("Could not write boot report {}: {}", path.display(), e),
+    }
 }
 
-fn boot_clr<T>(config: LoadConfig, args: Option<Vec<T>>) -> Result<i32, Box<dyn Error>> {
-    // println!("booting clr {:?}", config);
-    Ok(sabinokaku_common::init_clr(config, args)?)
+/// Handles a failure according to `policy`, where `code` is the exit code unless the policy gives one.
+fn apply_policy(policy: FailurePolicy, code: i32, message: std::fmt::Arguments) {
+    match policy {
+        FailurePolicy::Ignore => {}
+        FailurePolicy::Log => error!("{}.", message),
+        FailurePolicy::Exit(exit_code) => {
+            let exit_code = exit_code.unwrap_or(code);
+            error!("{}, exiting with {}.", message, exit_code);
+            std::process::exit(exit_code);
+        }
+        FailurePolicy::Abort => {
+            error!("{}, aborting.", message);
+            std::process::abort();
+        }
+    }
 }
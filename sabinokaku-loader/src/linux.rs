@@ -1,13 +1,21 @@
 #![cfg(all(target_os = "linux"))]
+use std::cell::Cell;
 use std::ffi::{c_void, CStr, OsString};
+#[cfg(feature = "vulkan")]
+use std::ffi::OsStr;
 use std::lazy::SyncOnceCell;
 use std::mem::MaybeUninit;
 use std::os::unix::ffi::OsStringExt;
 use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use libc::{c_char, c_int};
 
-use sabinokaku_common::config::ConfigSearchPath;
+use sabinokaku_common::boot::BootTrigger;
+use sabinokaku_common::config::{BootMode, ConfigSearchPath, ExitPolicy, HostContext, LoadConfig, DEFAULT_EXIT_TIMEOUT_MS,
+                                DEFAULT_READY_TIMEOUT_MS};
+use sabinokaku_common::report::BootReport;
 
 pub struct LinuxConfigSearchPath;
 impl ConfigSearchPath for LinuxConfigSearchPath {
@@ -24,6 +32,13 @@ impl ConfigSearchPath for LinuxConfigSearchPath {
         let os_str = OsString::from_vec(module_fname.into_bytes());
         PathBuf::from(os_str).canonicalize().ok()
     }
+
+    fn host_context() -> HostContext {
+        HostContext {
+            uid: Some(unsafe { libc::getuid() }),
+            ..HostContext::current()
+        }
+    }
 }
 
 type FnMain = extern "system" fn(c_int, *mut *mut c_char, *mut *mut c_char) -> c_int;
@@ -32,44 +47,139 @@ type FnLibcStartMain = extern "system" fn(FnMain, c_int, *mut *mut c_char, FnMai
 
 const LIBC_START_MAIN: &'static [u8] = b"__libc_start_main\0";
 
+/// The configuration file to boot from when preloaded, with the report its search was timed in, or `None` if there
+/// is nothing to boot.
+fn find_preload_config() -> Option<(PathBuf, BootReport)> {
+    let mut report = BootReport::new();
+    match crate::find_config(&mut report) {
+        Ok(cfg_path) => cfg_path.map(|cfg_path| (cfg_path, report)),
+        Err(e) => {
+            error!("Error occurred when loading config: {}", e);
+            None
+        }
+    }
+}
+
+/// Loads the configuration found by [`find_preload_config`], or `None` if there is nothing to boot.
+fn preload_config(cfg_path: PathBuf, report: BootReport) -> Option<LoadConfig> {
+    let config = match crate::load_config(cfg_path, report) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Error occurred when loading config: {}", e);
+            return None
+        }
+    };
+
+    #[cfg(feature = "vulkan")] {
+        if let Some(true) = std::env::var_os("ENABLE_SABINOKAKU_VULKAN").map(|s| s == OsStr::new("1")) {
+            debug!("Vulkan env enabled.");
+            if config.vulkan().is_some() {
+                info!("Vulkan config detected, disabling load entry.");
+                return None
+            }
+        }
+    }
+    Some(config)
+}
+
+thread_local! {
+    /// Whether the current thread boots the runtime, so that it does not wait for itself if it exits the process.
+    static IS_BOOT_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Set once the boot thread has finished, for [`ExitPolicy::Wait`].
+static BOOT_FINISHED: SyncOnceCell<crate::Signal> = SyncOnceCell::new();
+
+/// How long to wait for the boot thread at exit.
+static EXIT_TIMEOUT: SyncOnceCell<Duration> = SyncOnceCell::new();
+
+/// Spawns a thread to run `boot`, which the host process waits for when it exits if `on_exit` says so.
+fn spawn_boot(boot: impl FnOnce() -> i32 + Send + 'static, on_exit: ExitPolicy) -> JoinHandle<i32> {
+    if let ExitPolicy::Wait(timeout) = on_exit {
+        let timeout = timeout.unwrap_or(DEFAULT_EXIT_TIMEOUT_MS);
+        EXIT_TIMEOUT.get_or_init(|| Duration::from_millis(timeout.into()));
+        // Registered before the hook running shutdown callbacks, so that those run first and can tell managed
+        // code to finish.
+        at_exit(wait_for_boot_at_exit);
+    }
+    std::thread::spawn(move || {
+        IS_BOOT_THREAD.with(|is_boot_thread| is_boot_thread.set(true));
+        let code = boot();
+        BOOT_FINISHED.get_or_init(Default::default).set();
+        code
+    })
+}
+
+/// Registers `callback` to run when the host process exits, in the reverse order of registration.
+pub(crate) fn at_exit(callback: extern "C" fn()) {
+    if unsafe { libc::atexit(callback) } != 0 {
+        warn!("Could not register a hook to run at exit.");
+    }
+}
+
+extern "C" fn wait_for_boot_at_exit() {
+    // Exiting from the boot thread, such as by a failure policy, has nothing left to wait for.
+    if IS_BOOT_THREAD.with(Cell::get) {
+        return;
+    }
+    let timeout = EXIT_TIMEOUT.get().copied().unwrap_or_default();
+    debug!("Waiting up to {} ms for the boot thread to finish.", timeout.as_millis());
+    if !BOOT_FINISHED.get_or_init(Default::default).wait(timeout) {
+        warn!("Boot thread did not finish after {} ms, exiting anyway.", timeout.as_millis());
+    }
+}
+
 extern "system" fn thunked_main(argc: c_int, argv: *mut *mut c_char, envp: *mut *mut c_char) -> c_int {
-    // We don't wait for the thread. This is consistent with windows behaviour.
-    std::thread::spawn(|| {
-        let config = match crate::get_config() {
-            Ok(config) => config,
-            Err(e) => {
-                eprintln!("[libc_inject] Error occurred when injecting CLR: {}", e);
-                return 1
+    // Only how to order booting with `main` is read up front. The configuration is loaded along with booting, which
+    // happens on the boot thread unless booting on the main thread.
+    if let Some((cfg_path, report)) = find_preload_config() {
+        let (mode, on_exit) = LoadConfig::probe_boot_order(&cfg_path);
+        debug!("Booting in `{}` mode.", mode);
+        let boot = move || {
+            let config = match preload_config(cfg_path.clone(), report) {
+                Some(config) => config,
+                None => return 0
+            };
+            if (config.boot_mode(), config.on_exit()) != (mode, on_exit) {
+                warn!("`boot` and `on-exit` are only read from {}, not from the files it includes.", cfg_path.display());
             }
+            crate::boot_clr(config, BootTrigger::Preload, Vec::new())
         };
-
-        #[cfg(feature = "vulkan")] {
-            if let Some(true) = std::env::var_os("ENABLE_SABINOKAKU_VULKAN").map(|s| s == OsStr::new("1")) {
-                println!("[libc_inject] Vulkan env enabled.");
-                if config.vulkan().is_some() {
-                    println!("[libc_inject] Vulkan config detected, disabling load entry.");
-                    return 0
+        match mode {
+            // We don't wait for the thread. This is consistent with windows behaviour.
+            BootMode::Async => {
+                spawn_boot(boot, on_exit);
+            }
+            BootMode::Blocking => {
+                if spawn_boot(boot, ExitPolicy::Detach).join().is_err() {
+                    error!("Booting CLR panicked.");
                 }
             }
-        }
-
-        match crate::boot_clr::<()>(config,None) {
-            Ok(i) => {
-                i as u32
+            BootMode::Ready(timeout) => {
+                spawn_boot(move || {
+                    let code = boot();
+                    // Managed code may never signal if it failed, or only had work to do while booting.
+                    crate::sabinokaku_ready();
+                    code
+                }, on_exit);
+                let timeout = timeout.unwrap_or(DEFAULT_READY_TIMEOUT_MS);
+                let ready = crate::READY.get_or_init(Default::default);
+                if !ready.wait(Duration::from_millis(timeout.into())) {
+                    warn!("Managed code was not ready after {} ms, calling main.", timeout);
+                }
             }
-            Err(e) => {
-                eprintln!("[libc_inject] Error occurred when injecting CLR: {}", e);
-                1 as u32
+            BootMode::MainThread => {
+                boot();
             }
         }
-    });
+    }
 
     let ret = if let Some(real_main) = SAVED_MAIN.get() {
         real_main(argc, argv, envp)
     } else {
         // this should never happen but might as well exit if somehow we get launched into
         // here without main.
-        eprintln!("[libc_inject] No valid main entrypoint to inject into found.");
+        error!("No valid main entrypoint to inject into found.");
         1
     };
 
@@ -0,0 +1,163 @@
+//! Leveled logging, configured by the `log` and `log-to` directives, and [`LOG_ENV_VAR`].
+//!
+//! Nothing is logged unless asked for. Until a configuration is loaded, only [`LOG_ENV_VAR`] applies, logging to stderr.
+
+use std::fmt::Arguments;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::lazy::SyncOnceCell;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) use sabinokaku_common::services::LogLevel;
+use sabinokaku_common::config::{LoadConfig, LogSink};
+
+/// Overrides the level of the `log` directive, such as `SABINOKAKU_LOG=debug` or `SABINOKAKU_LOG=off`.
+pub(crate) const LOG_ENV_VAR: &str = "SABINOKAKU_LOG";
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::LogLevel::Error, format_args!($($arg)*)) }
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::LogLevel::Warn, format_args!($($arg)*)) }
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::LogLevel::Info, format_args!($($arg)*)) }
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::LogLevel::Debug, format_args!($($arg)*)) }
+}
+
+#[allow(unused_macros)]
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::LogLevel::Trace, format_args!($($arg)*)) }
+}
+
+struct Logger {
+    /// The most verbose level that is logged, or `None` to log nothing.
+    level: Option<LogLevel>,
+    sinks: Vec<Sink>,
+}
+
+enum Sink {
+    Stderr,
+    File(File),
+    #[cfg(target_os = "linux")]
+    Syslog,
+}
+
+static LOGGER: SyncOnceCell<Mutex<Logger>> = SyncOnceCell::new();
+
+fn logger() -> &'static Mutex<Logger> {
+    LOGGER.get_or_init(|| Mutex::new(Logger {
+        level: env_level().flatten(),
+        sinks: vec![Sink::Stderr],
+    }))
+}
+
+fn env_level() -> Option<Option<LogLevel>> {
+    std::env::var(LOG_ENV_VAR).ok().and_then(|level| LogLevel::parse_filter(level.trim()))
+}
+
+/// Logs as `config` asks from now on, where the level of [`LOG_ENV_VAR`] takes precedence over its `log` directive.
+pub(crate) fn configure(config: &LoadConfig) {
+    let mut problems = Vec::new();
+    let sinks = config.log_sinks().into_iter()
+        .filter_map(|sink| match sink {
+            LogSink::Stderr => Some(Sink::Stderr),
+            LogSink::File(path) => match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => Some(Sink::File(file)),
+                Err(e) => {
+                    problems.push(format!("Could not open log file {}: {}", path.display(), e));
+                    None
+                }
+            },
+            #[cfg(target_os = "linux")]
+            LogSink::Syslog => Some(Sink::Syslog),
+            #[cfg(not(target_os = "linux"))]
+            LogSink::Syslog => {
+                problems.push(String::from("Logging to syslog is only supported on Linux"));
+                None
+            }
+        })
+        .collect();
+
+    let level = env_level().unwrap_or_else(|| config.log_level());
+    if let Ok(mut logger) = logger().lock() {
+        *logger = Logger { level, sinks };
+    }
+    for problem in problems {
+        warn!("{}", problem);
+    }
+}
+
+/// Logs `message` to every sink if `level` is enabled. Use the `error!` to `trace!` macros instead.
+pub(crate) fn write(level: LogLevel, message: Arguments) {
+    let mut logger = match logger().lock() {
+        Ok(logger) => logger,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if !matches!(logger.level, Some(max) if level <= max) {
+        return;
+    }
+
+    let message = message.to_string();
+    for sink in &mut logger.sinks {
+        match sink {
+            Sink::Stderr => write_stderr(level, &message),
+            Sink::File(file) => {
+                let _ = writeln!(file, "{} [{}] {}: {}", timestamp(), std::process::id(), level, message);
+            }
+            #[cfg(target_os = "linux")]
+            Sink::Syslog => write_syslog(level, &message),
+        }
+    }
+}
+
+fn write_stderr(level: LogLevel, message: &str) {
+    // GUI processes have no console, so one is allocated for errors to be seen at all.
+    #[cfg(target_os = "windows")]
+    if level == LogLevel::Error {
+        unsafe { winapi::um::consoleapi::AllocConsole(); }
+    }
+    eprintln!("[sabinokaku] [{}] {}", level, message);
+}
+
+#[cfg(target_os = "linux")]
+fn write_syslog(level: LogLevel, message: &str) {
+    use std::ffi::CString;
+
+    let priority = match level {
+        LogLevel::Error => libc::LOG_ERR,
+        LogLevel::Warn => libc::LOG_WARNING,
+        LogLevel::Info => libc::LOG_INFO,
+        LogLevel::Debug | LogLevel::Trace => libc::LOG_DEBUG,
+    };
+    // `openlog` is left to the host process, so messages are tagged here instead.
+    if let Ok(message) = CString::new(format!("sabinokaku[{}]: {}", std::process::id(), message)) {
+        unsafe { libc::syslog(libc::LOG_USER | priority, b"%s\0".as_ptr() as *const libc::c_char, message.as_ptr()) }
+    }
+}
+
+/// The current time in UTC as `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, seconds) = ((now.as_secs() / 86400) as i64, now.as_secs() % 86400);
+
+    // Converts days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+            seconds / 3600, seconds / 60 % 60, seconds % 60, now.subsec_millis())
+}
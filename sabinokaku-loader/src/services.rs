@@ -0,0 +1,111 @@
+//! The services table passed to managed code in the boot info, see [`SabinokakuServices`].
+
+use std::ffi::c_void;
+use std::lazy::SyncOnceCell;
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::Mutex;
+
+use sabinokaku_common::pdcstring::{PdChar, PdCStr, PdCString};
+use sabinokaku_common::prelude::*;
+
+pub(crate) static SERVICES: SabinokakuServices = SabinokakuServices {
+    version: SERVICES_VERSION,
+    size: size_of::<SabinokakuServices>() as u32,
+    log,
+    config,
+    custom_directive,
+    status: crate::api::sabinokaku_status,
+    on_shutdown,
+    ready: crate::sabinokaku_ready,
+};
+
+/// What managed code can read of the configuration that was booted, kept for the lifetime of the process.
+struct ConfigSnapshot {
+    text: Option<PdCString>,
+    custom: Vec<(String, PdCString)>,
+}
+
+static SNAPSHOT: SyncOnceCell<ConfigSnapshot> = SyncOnceCell::new();
+
+/// Registered callbacks and their contexts, or `None` once they have been run.
+type ShutdownCallbacks = Mutex<Option<Vec<(ShutdownCallback, usize)>>>;
+
+static SHUTDOWN_CALLBACKS: SyncOnceCell<ShutdownCallbacks> = SyncOnceCell::new();
+
+/// Keeps what managed code can read of `config`, where `cfg_path` is the file it was loaded from.
+pub(crate) fn snapshot_config(config: &LoadConfig, cfg_path: Option<&Path>) {
+    let text = cfg_path
+        .and_then(|path| config.write(path, ConfigFormat::Long).ok())
+        .and_then(|text| PdCString::from_os_str(text).ok());
+    let custom = config.additional_params.iter()
+        .filter_map(|p| match p {
+            AdditionalParameter::Custom(k, v) => Some((k.clone(), PdCString::from_os_str(v).ok()?)),
+            _ => None
+        })
+        .collect();
+    SNAPSHOT.get_or_init(|| ConfigSnapshot { text, custom });
+}
+
+/// Makes sure shutdown callbacks run when the host process exits. On Windows, `DllMain` runs them instead when
+/// `kaku.dll` is unloaded with `FreeLibrary`, and they are skipped when the process terminates.
+pub(crate) fn register_shutdown() {
+    SHUTDOWN_CALLBACKS.get_or_init(|| Mutex::new(Some(Vec::new())));
+    #[cfg(target_os = "linux")]
+    crate::linux::at_exit(run_shutdown_callbacks_at_exit);
+}
+
+/// Calls every registered shutdown callback once, in the reverse order of registration.
+pub(crate) fn run_shutdown_callbacks() {
+    let callbacks = match SHUTDOWN_CALLBACKS.get() {
+        Some(callbacks) => callbacks.lock().map_or(None, |mut callbacks| callbacks.take()),
+        None => return
+    };
+    for (callback, context) in callbacks.unwrap_or_default().into_iter().rev() {
+        unsafe { callback(context as *mut c_void) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+extern "C" fn run_shutdown_callbacks_at_exit() {
+    run_shutdown_callbacks()
+}
+
+unsafe extern "C" fn log(level: u32, message: *const PdChar) {
+    if message.is_null() {
+        return;
+    }
+    let message = PdCStr::from_str_ptr(message).to_os_string();
+    crate::log::write(LogLevel::from_u32(level), format_args!("[managed] {}", message.to_string_lossy()));
+}
+
+extern "C" fn config() -> *const PdChar {
+    SNAPSHOT.get()
+        .and_then(|snapshot| snapshot.text.as_ref())
+        .map_or(std::ptr::null(), |text| text.as_ptr())
+}
+
+unsafe extern "C" fn custom_directive(name: *const PdChar, index: usize) -> *const PdChar {
+    let (snapshot, name) = match (SNAPSHOT.get(), name.is_null()) {
+        (Some(snapshot), false) => (snapshot, PdCStr::from_str_ptr(name).to_os_string()),
+        _ => return std::ptr::null()
+    };
+    snapshot.custom.iter()
+        .filter(|(k, _)| name == k.as_str())
+        .nth(index)
+        .map_or(std::ptr::null(), |(_, v)| v.as_ptr())
+}
+
+unsafe extern "C" fn on_shutdown(callback: ShutdownCallback, context: *mut c_void) -> i32 {
+    let callbacks = match SHUTDOWN_CALLBACKS.get() {
+        Some(callbacks) => callbacks,
+        None => return 0
+    };
+    match callbacks.lock().as_deref_mut() {
+        Ok(Some(callbacks)) => {
+            callbacks.push((callback, context as usize));
+            1
+        }
+        _ => 0
+    }
+}
@@ -4,6 +4,7 @@ use std::lazy::{SyncLazy, SyncOnceCell};
 use std::sync::RwLock;
 use ash::vk;
 use ash::vk::{Handle, Result};
+use sabinokaku_common::boot::BootTrigger;
 use sabinokaku_common::config::{LoadConfig, VulkanEntryPoint};
 use std::os::raw::c_char;
 use std::thread;
@@ -101,7 +102,7 @@ unsafe extern "system" fn create_instance(
     p_allocator: *const vk::AllocationCallbacks,
     p_instance: *mut vk::Instance,
 ) -> vk::Result {
-    println!("[sk] create_instance");
+    trace!("vkCreateInstance");
     let instance_info = p_create_info.as_ref().unwrap();
 
     let mut layer_info = instance_info.p_next.cast::<VkLayerInstanceCreateInfo>();
@@ -174,7 +175,7 @@ unsafe extern "system" fn create_device(
     p_allocator: *const vk::AllocationCallbacks,
     p_device: *mut vk::Device,
 ) -> vk::Result {
-    println!("[sk] create_device");
+    trace!("vkCreateDevice");
 
     let instance_info = p_create_info.as_ref().unwrap();
 
@@ -283,20 +284,16 @@ fn clr_entry_point(handles: Vec<u64>) {
         let config = match LOAD_CONFIG.get() {
             Some(config) => config,
             None => {
-                eprintln!("[vk_inject] Error occurred when injecting CLR");
+                error!("Error occurred when injecting CLR, no configuration was loaded.");
                 return 1
             }
         };
 
-        match crate::boot_clr(config.clone(),Some(handles)) {
-            Ok(i) => {
-                i as u32
-            }
-            Err(e) => {
-                eprintln!("[vk_inject] Error occurred when injecting CLR: {}", e);
-                1 as u32
-            }
-        }
+        let trigger = match config.vulkan() {
+            Some(vulkan) => BootTrigger::from(vulkan.entry),
+            None => BootTrigger::VkCreateInstance,
+        };
+        crate::boot_clr(config.clone(), trigger, handles) as u32
     });
 }
 
@@ -309,7 +306,7 @@ pub unsafe extern "system" fn sabinokaku_negotiate_layer_version(interface: *mut
     }
 
     if let Some(true) = std::env::var_os("SABINOKAKU_VULKAN_BOOTED").map(|s| s == OsStr::new("1")) {
-        eprintln!("[dllmain_inject] Vulkan env already initialized.");
+        warn!("Vulkan env already initialized.");
         return Result::ERROR_INITIALIZATION_FAILED;
     }
 
@@ -325,7 +322,7 @@ pub unsafe extern "system" fn sabinokaku_negotiate_layer_version(interface: *mut
         return Result::ERROR_INITIALIZATION_FAILED;
     }
 
-    if let Ok(config) = crate::get_config() {
+    if let Ok(Some(config)) = crate::get_config() {
         // Validate init params
         let vk_cfg = config.vulkan();
         if vk_cfg.is_none() {
@@ -343,9 +340,10 @@ pub unsafe extern "system" fn sabinokaku_negotiate_layer_version(interface: *mut
         (*interface).pfn_get_physical_device_proc_addr = None;
 
         LOAD_CONFIG.get_or_init(move || config);
-        println!("Negotiate OK {:?}\n IPA {:p}\n {:p}", LOAD_CONFIG.get(), (*interface).pfn_get_instance_proc_addr as *const (),  (*interface).pfn_get_device_proc_addr as *const ());
+        debug!("Negotiated layer interface version {}, IPA {:p}, DPA {:p}.", (*interface).loader_layer_interface_version,
+               (*interface).pfn_get_instance_proc_addr as *const (), (*interface).pfn_get_device_proc_addr as *const ());
         return Result::SUCCESS
     }
-    println!("failed vulkan");
+    warn!("Could not load a configuration for the Vulkan layer.");
     Result::ERROR_INITIALIZATION_FAILED
 }
\ No newline at end of file
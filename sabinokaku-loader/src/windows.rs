@@ -9,9 +9,10 @@ use winapi::shared::ntdef::*;
 use winapi::um::libloaderapi::{DisableThreadLibraryCalls, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
                                GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
                                GetModuleFileNameW, GetModuleHandleExW};
-use winapi::um::winnt::DLL_PROCESS_ATTACH;
+use winapi::um::winnt::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH};
 
-use sabinokaku_common::config::ConfigSearchPath;
+use sabinokaku_common::boot::BootTrigger;
+use sabinokaku_common::config::{BootMode, ConfigSearchPath, ExitPolicy};
 
 pub struct WindowsConfigSearchPath;
 impl ConfigSearchPath for WindowsConfigSearchPath {
@@ -47,39 +48,44 @@ impl ConfigSearchPath for WindowsConfigSearchPath {
 pub extern "system" fn DllMain(
     module: HINSTANCE,
     call_reason: DWORD,
-    _reserved: LPVOID,
+    reserved: LPVOID,
 ) -> BOOL {
     unsafe { DisableThreadLibraryCalls(module); }
     if call_reason == DLL_PROCESS_ATTACH {
         std::thread::spawn(|| {
             let config = match crate::get_config() {
-                Ok(config) => config,
+                Ok(Some(config)) => config,
+                Ok(None) => return 0,
                 Err(e) => {
-                    unsafe { winapi::um::consoleapi::AllocConsole(); }
-                    eprintln!("[dllmain_inject] Error occurred when parsing config: {}", e);
+                    error!("Error occurred when parsing config: {}", e);
                     return 1
                 }
             };
             
             #[cfg(feature = "vulkan")] {
                 if let Some(true) = std::env::var_os("ENABLE_SABINOKAKU_VULKAN").map(|s| s == OsStr::new("1")) {
-                    eprintln!("[dllmain_inject] Vulkan env enabled.");
+                    debug!("Vulkan env enabled.");
                     if config.vulkan().is_some() {
-                        eprintln!("[dllmain_inject] Vulkan config detected, disabling load entry.");
+                        info!("Vulkan config detected, disabling load entry.");
                         return 0
                     }
                 }
             }
 
-            match crate::boot_clr::<()>(config, None) {
-                Ok(i) => i as u32,
-                Err(e) => {
-                    unsafe { winapi::um::consoleapi::AllocConsole(); }
-                    eprintln!("[dllmain_inject] Error occurred when injecting CLR: {}", e);
-                    1
-                }
+            if config.boot_mode() != BootMode::Async {
+                // The loader lock is held in `DllMain`, so there is no `main` to hold back safely.
+                warn!("`boot {}` is only supported on Linux, booting asynchronously.", config.boot_mode());
+            }
+            if config.on_exit() != ExitPolicy::Detach {
+                warn!("`on-exit {}` is only supported on Linux, not waiting at exit.", config.on_exit());
             }
+            crate::boot_clr(config, BootTrigger::DllMain, Vec::new()) as u32
         });
+    } else if call_reason == DLL_PROCESS_DETACH && reserved.is_null() {
+        // `reserved` is only null when `kaku.dll` is unloaded with `FreeLibrary`. When the process is terminating,
+        // every other thread is already gone and the loader lock is held, so callbacks touching managed code would
+        // deadlock; they are skipped instead.
+        crate::services::run_shutdown_callbacks();
     }
     winapi::shared::minwindef::TRUE
 }
@@ -0,0 +1,15 @@
+use std::path::Path;
+
+/// `include/sabinokaku.h` is generated by cbindgen, and must be regenerated whenever the C API changes.
+#[test]
+fn test_header_up_to_date() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+    let mut generated = Vec::new();
+    cbindgen::generate_with_config(crate_dir, config).unwrap().write(&mut generated);
+
+    let committed = std::fs::read(crate_dir.join("include/sabinokaku.h")).unwrap();
+    assert!(committed == generated,
+            "include/sabinokaku.h is out of date, run `cbindgen --config cbindgen.toml --output include/sabinokaku.h` \
+             in sabinokaku-loader");
+}